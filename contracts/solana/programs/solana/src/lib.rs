@@ -1,9 +1,15 @@
 #![allow(unexpected_cfgs)]
 use anchor_lang::prelude::*;
+use anchor_lang::system_program;
+use anchor_lang::Discriminator;
+use anchor_spl::token::{self, Mint, Token, TokenAccount};
 use anchor_lang::solana_program::{
     keccak,
     ed25519_program,
-    sysvar::instructions::{load_instruction_at_checked, ID as IX_ID},
+    instruction::Instruction,
+    native_token::LAMPORTS_PER_SOL,
+    secp256k1_program,
+    sysvar::instructions::{load_current_index_checked, load_instruction_at_checked, ID as IX_ID},
 };
 
 declare_id!("GRLdEPx7n4g2kowPvfPrPWpToeap3sHbKSDe18bCLyU5");
@@ -11,129 +17,819 @@ declare_id!("GRLdEPx7n4g2kowPvfPrPWpToeap3sHbKSDe18bCLyU5");
 #[program]
 pub mod datahaven_solana {
     use super::*;
-    pub fn initialize(ctx: Context<Initialize>) -> Result<()> {
+    /// Sets up the core accounts with default tunables; pricing and limits
+    /// are set afterwards with `configure`. `network_tag` is an
+    /// operator-chosen id for the deployment (devnet, mainnet, ...), echoed in
+    /// request events for shared indexers.
+    pub fn initialize(ctx: Context<Initialize>, relayer: Pubkey, network_tag: u8) -> Result<()> {
+        require!(relayer != Pubkey::default(), ErrorCode::InvalidRelayer);
         let state = &mut ctx.accounts.state;
         state.admin = ctx.accounts.admin.key();
-        state.relayer = ctx.accounts.admin.key();
-        state.paused = false;
+        state.relayer = relayer;
+        state.pause_flags = 0;
         state.count = 0;
+        state.relayers[0] = state.relayer;
+        state.relayer_count = 1;
+        state.min_payment = DEFAULT_MIN_PAYMENT;
+        state.timeout_secs = DEFAULT_TIMEOUT_SECS;
+        state.threshold = 1;
+        state.state_bump = ctx.bumps.state;
+        state.vault_bump = ctx.bumps.vault;
+        state.max_payment = DEFAULT_MAX_PAYMENT;
+        state.version = STATE_VERSION;
+        state.network_tag = network_tag;
         Ok(())
     }
+    /// Sets any of the tunables in `params` in one go, leaving the `None` ones
+    /// alone, so it can safely be re-run.
+    pub fn configure(ctx: Context<AdminAction>, params: ConfigParams) -> Result<()> {
+        let state = &mut ctx.accounts.state;
+        if let Some(fee_bps) = params.fee_bps {
+            require!(fee_bps <= MAX_BPS, ErrorCode::InvalidFeeBps);
+            state.fee_bps = fee_bps;
+        }
+        if let Some(failure_fee_bps) = params.failure_fee_bps {
+            require!(failure_fee_bps <= MAX_BPS, ErrorCode::InvalidFeeBps);
+            state.failure_fee_bps = failure_fee_bps;
+        }
+        if let Some(timeout_secs) = params.timeout_secs {
+            require!(timeout_secs >= 0, ErrorCode::InvalidDuration);
+            state.timeout_secs = timeout_secs;
+        }
+        if let Some(confirm_window_secs) = params.confirm_window_secs {
+            require!(confirm_window_secs >= 0, ErrorCode::InvalidDuration);
+            state.confirm_window_secs = confirm_window_secs;
+        }
+        set_payment_bounds(state, params.min_payment, params.max_payment)?;
+        if let Some(base_fee) = params.base_fee {
+            state.base_fee = base_fee;
+        }
+        if let Some(per_byte_fee) = params.per_byte_fee {
+            state.per_byte_fee = per_byte_fee;
+        }
+        if let Some(max_pending_per_user) = params.max_pending_per_user {
+            state.max_pending_per_user = max_pending_per_user;
+        }
+        if let Some(max_data_size) = params.max_data_size {
+            state.max_data_size = max_data_size;
+        }
+        emit!(Configured { params });
+        Ok(())
+    }
+    /// Reallocates a `State` and `Vault` created by an older build to the
+    /// current layout and stamps `STATE_VERSION`. Fields they predate get the
+    /// defaults `initialize` uses wherever zero would mean something else.
+    pub fn migrate_state(ctx: Context<MigrateState>) -> Result<()> {
+        let state_ai = ctx.accounts.state.to_account_info();
+        require_keys_eq!(*state_ai.owner, crate::ID, ErrorCode::Unauthorized);
+        // `admin` is the first field, so it can be checked before the account
+        // is large enough to deserialize.
+        let admin = {
+            let data = state_ai.try_borrow_data()?;
+            require!(
+                data.len() >= 40 && data[..8] == State::DISCRIMINATOR,
+                ErrorCode::InvalidStateAccount
+            );
+            Pubkey::try_from_slice(&data[8..40])?
+        };
+        require_keys_eq!(admin, ctx.accounts.admin.key(), ErrorCode::Unauthorized);
+        let vault_ai = ctx.accounts.vault.to_account_info();
+        require!(
+            *vault_ai.owner == crate::ID
+                && vault_ai.try_borrow_data()?.starts_with(&Vault::DISCRIMINATOR),
+            ErrorCode::InvalidStateAccount
+        );
+        for (account, space) in [
+            (&state_ai, 8 + State::INIT_SPACE),
+            (&vault_ai, 8 + Vault::INIT_SPACE),
+        ] {
+            grow_account(account, space, &ctx.accounts.admin, &ctx.accounts.system_program)?;
+        }
+        let mut state = State::try_deserialize(&mut &state_ai.try_borrow_data()?[..])?;
+        require!(state.version < STATE_VERSION, ErrorCode::AlreadyMigrated);
+        state.state_bump = ctx.bumps.state;
+        state.vault_bump = ctx.bumps.vault;
+        state.fill_migration_defaults();
+        state.version = STATE_VERSION;
+        state.try_serialize(&mut &mut state_ai.try_borrow_mut_data()?[..])?;
+        emit!(StateMigrated {
+            version: STATE_VERSION,
+        });
+        Ok(())
+    }
+    #[allow(clippy::too_many_arguments)]
     pub fn initiate_storage(
         ctx: Context<InitiateStorage>,
         data_hash: [u8; 32],
         payment_amount: u64,
+        data_size: u64,
+        duration_secs: i64,
+        content_type: [u8; 16],
+        enc_key_commitment: [u8; 32],
+        dedup: bool,
+        priority: u8,
+        max_acceptable_payment: Option<u64>,
+        memo: [u8; 32],
+        approved_relayer: Pubkey,
+        tier: u8,
+        refund_to: Pubkey,
+        tags: [u8; 4],
     ) -> Result<()> {
-        require!(!ctx.accounts.state.paused, ErrorCode::Paused);
-        require!(payment_amount >= MIN_PAYMENT, ErrorCode::InsufficientPayment);
-        let user_ai = ctx.accounts.user.to_account_info();
-        let vault_ai = ctx.accounts.vault.to_account_info();
-        **user_ai.lamports.borrow_mut() -= payment_amount;
-        **vault_ai.lamports.borrow_mut() += payment_amount;
-        let req = &mut ctx.accounts.request;
-        req.user = ctx.accounts.user.key();
-        req.data_hash = data_hash;
-        req.blob_id = [0u8; 32];
-        req.sui_tx_hash = [0u8; 32];
-        req.proof_hash = [0u8; 32];
-        req.status = Status::Pending;
-        req.payment = payment_amount;
-        req.timestamp = Clock::get()?.unix_timestamp;
-        ctx.accounts.state.count += 1;
-        emit!(StorageRequested {
-            request_id: req.key(),
-            user: req.user,
-            data_hash,
-            payment: payment_amount,
-            timestamp: req.timestamp,
-        });
-        Ok(())
+        open_single_request(
+            &mut ctx.accounts.state,
+            &mut ctx.accounts.user_state,
+            &mut ctx.accounts.request,
+            &ctx.accounts.hash_registry,
+            ctx.accounts.user.key(),
+            dedup,
+            NewRequest {
+                data_hash,
+                payment: payment_amount,
+                data_size,
+                duration_secs,
+                content_type,
+                enc_key_commitment,
+                priority,
+                max_acceptable_payment,
+                memo,
+                approved_relayer,
+                tier,
+                refund_to,
+                tags,
+            },
+        )?;
+        fund_native_request(
+            &mut ctx.accounts.state,
+            &ctx.accounts.request,
+            &ctx.accounts.system_program,
+            &ctx.accounts.user,
+            &ctx.accounts.escrow.to_account_info(),
+            &mut ctx.accounts.vault,
+        )
+    }
+    /// Like `initiate_storage`, but `sponsor` pays the rent and the payment so
+    /// the owning `user` needs no SOL. Refunds and reclaimed rent still go to
+    /// the owner.
+    #[allow(clippy::too_many_arguments)]
+    pub fn initiate_storage_sponsored(
+        ctx: Context<InitiateStorageSponsored>,
+        data_hash: [u8; 32],
+        payment_amount: u64,
+        data_size: u64,
+        duration_secs: i64,
+        content_type: [u8; 16],
+        enc_key_commitment: [u8; 32],
+        dedup: bool,
+        priority: u8,
+        max_acceptable_payment: Option<u64>,
+        memo: [u8; 32],
+        approved_relayer: Pubkey,
+        tier: u8,
+        refund_to: Pubkey,
+        tags: [u8; 4],
+    ) -> Result<()> {
+        open_single_request(
+            &mut ctx.accounts.state,
+            &mut ctx.accounts.user_state,
+            &mut ctx.accounts.request,
+            &ctx.accounts.hash_registry,
+            ctx.accounts.user.key(),
+            dedup,
+            NewRequest {
+                data_hash,
+                payment: payment_amount,
+                data_size,
+                duration_secs,
+                content_type,
+                enc_key_commitment,
+                priority,
+                max_acceptable_payment,
+                memo,
+                approved_relayer,
+                tier,
+                refund_to,
+                tags,
+            },
+        )?;
+        fund_native_request(
+            &mut ctx.accounts.state,
+            &ctx.accounts.request,
+            &ctx.accounts.system_program,
+            &ctx.accounts.sponsor,
+            &ctx.accounts.escrow.to_account_info(),
+            &mut ctx.accounts.vault,
+        )
+    }
+    #[allow(clippy::too_many_arguments)]
+    pub fn initiate_storage_spl(
+        ctx: Context<InitiateStorageSpl>,
+        data_hash: [u8; 32],
+        amount: u64,
+        data_size: u64,
+        duration_secs: i64,
+        content_type: [u8; 16],
+        enc_key_commitment: [u8; 32],
+        dedup: bool,
+        priority: u8,
+        max_acceptable_payment: Option<u64>,
+        memo: [u8; 32],
+        approved_relayer: Pubkey,
+        tier: u8,
+        tags: [u8; 4],
+    ) -> Result<()> {
+        open_single_request(
+            &mut ctx.accounts.state,
+            &mut ctx.accounts.user_state,
+            &mut ctx.accounts.request,
+            &ctx.accounts.hash_registry,
+            ctx.accounts.user.key(),
+            dedup,
+            NewRequest {
+                data_hash,
+                payment: amount,
+                data_size,
+                duration_secs,
+                content_type,
+                enc_key_commitment,
+                priority,
+                max_acceptable_payment,
+                memo,
+                approved_relayer,
+                tier,
+                // Token refunds always go to the owner's token account.
+                refund_to: Pubkey::default(),
+                tags,
+            },
+        )?;
+        ctx.accounts.request.payment_mint = ctx.accounts.mint.key();
+        let payment = ctx.accounts.request.payment;
+        ctx.accounts.state.lock_tokens(payment)?;
+        collect_creation_fee(
+            &mut ctx.accounts.state,
+            ctx.accounts.request.key(),
+            &ctx.accounts.system_program,
+            &ctx.accounts.user,
+            &mut ctx.accounts.vault,
+        )?;
+        token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                token::Transfer {
+                    from: ctx.accounts.user_token.to_account_info(),
+                    to: ctx.accounts.vault_token.to_account_info(),
+                    authority: ctx.accounts.user.to_account_info(),
+                },
+            ),
+            ctx.accounts.request.payment,
+        )?;
+        emit_storage_requested(
+            &ctx.accounts.state,
+            &ctx.accounts.request,
+            ctx.accounts.vault.get_lamports(),
+        );
+        check_vault_invariant(&ctx.accounts.state, &ctx.accounts.vault.to_account_info())
+    }
+    /// Opens one native request per item, all or nothing. `remaining_accounts`
+    /// holds an uninitialized `[request, escrow]` pair per item, at the PDAs of
+    /// the owner's next request indices in order.
+    pub fn initiate_storage_batch<'info>(
+        ctx: Context<'_, '_, 'info, 'info, InitiateStorageBatch<'info>>,
+        items: Vec<StorageItem>,
+        duration_secs: i64,
+        priority: u8,
+        tier: u8,
+    ) -> Result<()> {
+        require!(
+            !items.is_empty()
+                && items.len() <= MAX_BATCH_SIZE
+                && ctx.remaining_accounts.len() == items.len() * 2,
+            ErrorCode::InvalidBatch
+        );
+        // A batch counts as a single submission.
+        ctx.accounts.user_state.throttle(&ctx.accounts.state)?;
+        let user = ctx.accounts.user.key();
+        for (item, accounts) in items.iter().zip(ctx.remaining_accounts.chunks(2)) {
+            let (request_ai, escrow_ai) = (&accounts[0], &accounts[1]);
+            let index = ctx.accounts.user_state.request_count.to_le_bytes();
+            let (request_key, request_bump) =
+                request_address(&user, ctx.accounts.user_state.request_count);
+            require_keys_eq!(request_ai.key(), request_key, ErrorCode::InvalidBatch);
+            let (escrow_key, escrow_bump) =
+                Pubkey::find_program_address(&[b"escrow", request_key.as_ref()], &crate::ID);
+            require_keys_eq!(escrow_ai.key(), escrow_key, ErrorCode::InvalidBatch);
+            create_program_account(
+                &ctx.accounts.system_program,
+                &ctx.accounts.user,
+                request_ai,
+                8 + Request::INIT_SPACE,
+                &Request::DISCRIMINATOR,
+                &[b"request", user.as_ref(), &index, &[request_bump]],
+            )?;
+            create_program_account(
+                &ctx.accounts.system_program,
+                &ctx.accounts.user,
+                escrow_ai,
+                8,
+                &Escrow::DISCRIMINATOR,
+                &[b"escrow", request_key.as_ref(), &[escrow_bump]],
+            )?;
+            let mut req = Account::<Request>::try_from(request_ai)?;
+            open_request(
+                &mut ctx.accounts.state,
+                &mut ctx.accounts.user_state,
+                &mut req,
+                user,
+                NewRequest {
+                    data_hash: item.data_hash,
+                    payment: item.payment_amount,
+                    data_size: item.data_size,
+                    duration_secs,
+                    content_type: item.content_type,
+                    enc_key_commitment: item.enc_key_commitment,
+                    priority,
+                    max_acceptable_payment: None,
+                    memo: [0u8; 32],
+                    approved_relayer: Pubkey::default(),
+                    tier,
+                    refund_to: Pubkey::default(),
+                    tags: item.tags,
+                },
+            )?;
+            let payment = req.payment;
+            ctx.accounts.state.lock_payment(payment)?;
+            req.exit(&crate::ID)?;
+            collect_creation_fee(
+                &mut ctx.accounts.state,
+                request_key,
+                &ctx.accounts.system_program,
+                &ctx.accounts.user,
+                &mut ctx.accounts.vault,
+            )?;
+            deposit(&ctx.accounts.system_program, &ctx.accounts.user, escrow_ai, payment)?;
+            emit_storage_requested(&ctx.accounts.state, &req, ctx.accounts.vault.get_lamports());
+        }
+        check_vault_invariant(&ctx.accounts.state, &ctx.accounts.vault.to_account_info())
     }
     pub fn verify_receipt(
         ctx: Context<UpdateStatus>,
         blob_id: [u8; 32],
         sui_tx_hash: [u8; 32],
         proof_hash: [u8; 32],
+        sui_checkpoint: u64,
+        deadline: i64,
+        sui_object_ids: [[u8; 32]; 2],
     ) -> Result<()> {
         let req = &mut ctx.accounts.request;
-        require!(req.status == Status::Pending, ErrorCode::InvalidStatus);
-        
-        // Build the message to verify
-        let message = keccak::hashv(&[
-            &req.key().to_bytes(),
-            &blob_id,
-            &sui_tx_hash,
-            &proof_hash,
-        ]).0;
-        
-        // Verify the Ed25519 signature using the instruction sysvar
-        // The signature verification must be done via the Ed25519Program
-        // which should be called before this instruction
-        let ix_sysvar = &ctx.accounts.instruction_sysvar;
-        
-        // Load the previous instruction (should be Ed25519 verify)
-        let ix = load_instruction_at_checked(0, ix_sysvar)?;
-        
-        // Verify it's the Ed25519 program
-        require!(
-            ix.program_id == ed25519_program::ID,
-            ErrorCode::InvalidSignature
-        );
-        
-        // Ed25519 instruction data format:
-        // [0]: number of signatures (u8)
-        // [1]: padding (u8)
-        // [2..4]: signature offset (u16)
-        // [4..6]: signature instruction index (u16)
-        // [6..8]: public key offset (u16)
-        // [8..10]: public key instruction index (u16)
-        // [10..12]: message data offset (u16)
-        // [12..14]: message data size (u16)
-        // [14..16]: message instruction index (u16)
-        
-        require!(ix.data.len() >= 16, ErrorCode::InvalidSignature);
-        
-        // Verify the message matches what we expect
-        let msg_start = u16::from_le_bytes([ix.data[10], ix.data[11]]) as usize;
-        let msg_size = u16::from_le_bytes([ix.data[12], ix.data[13]]) as usize;
-        
-        require!(
-            msg_size == 32 && 
-            ix.data.len() >= msg_start + msg_size &&
-            &ix.data[msg_start..msg_start + msg_size] == message.as_slice(),
-            ErrorCode::InvalidSignature
-        );
-        
-        // Verify the public key matches the relayer
-        let pk_start = u16::from_le_bytes([ix.data[6], ix.data[7]]) as usize;
+        require!(req.is_pending(), ErrorCode::InvalidStatus);
+        let receipt = Receipt {
+            blob_id,
+            sui_tx_hash,
+            proof_hash,
+            sui_checkpoint,
+            deadline,
+            sui_object_ids,
+        };
+        let message = build_receipt_message(&req.key(), &receipt, req.nonce);
+        let signers = require_relayer_quorum(
+            &ctx.accounts.state,
+            &ctx.accounts.instruction_sysvar,
+            &message,
+        )?;
+        confirm_request(
+            &mut ctx.accounts.state,
+            &mut ctx.accounts.user_state,
+            req,
+            &ctx.accounts.escrow.to_account_info(),
+            &mut ctx.accounts.vault,
+            &receipt,
+            req.approved_signer(&signers)?,
+        )
+    }
+    pub fn verify_receipt_secp256k1(
+        ctx: Context<UpdateStatus>,
+        blob_id: [u8; 32],
+        sui_tx_hash: [u8; 32],
+        proof_hash: [u8; 32],
+        sui_checkpoint: u64,
+        deadline: i64,
+        sui_object_ids: [[u8; 32]; 2],
+    ) -> Result<()> {
+        let req = &mut ctx.accounts.request;
+        require!(req.is_pending(), ErrorCode::InvalidStatus);
+        let receipt = Receipt {
+            blob_id,
+            sui_tx_hash,
+            proof_hash,
+            sui_checkpoint,
+            deadline,
+            sui_object_ids,
+        };
+        let message = build_receipt_message(&req.key(), &receipt, req.nonce);
+        require_eth_attestation(&ctx.accounts.state, &ctx.accounts.instruction_sysvar, &message)?;
+        // The Ethereum key has no Solana identity, so credit the enrolled
+        // relayer that submitted it.
+        let signer = req.approved_signer(&[ctx.accounts.relayer.key()])?;
+        confirm_request(
+            &mut ctx.accounts.state,
+            &mut ctx.accounts.user_state,
+            req,
+            &ctx.accounts.escrow.to_account_info(),
+            &mut ctx.accounts.vault,
+            &receipt,
+            signer,
+        )
+    }
+    /// `remaining_accounts` holds one `[request, escrow, user_state]` triple per
+    /// receipt, each signed by `threshold` relayers with Ed25519 verify
+    /// instructions anywhere ahead of this one.
+    pub fn verify_receipt_batch<'info>(
+        ctx: Context<'_, '_, 'info, 'info, UpdateStatusBatch<'info>>,
+        receipts: Vec<Receipt>,
+    ) -> Result<()> {
         require!(
-            ix.data.len() >= pk_start + 32 &&
-            &ix.data[pk_start..pk_start + 32] == ctx.accounts.state.relayer.as_ref(),
-            ErrorCode::InvalidSignature
+            !receipts.is_empty()
+                && receipts.len() <= MAX_BATCH_SIZE
+                && ctx.remaining_accounts.len() == receipts.len() * 3,
+            ErrorCode::InvalidBatch
         );
-        
+        let attestations =
+            collect_attestations(&ctx.accounts.instruction_sysvar, ed25519_attestation)?;
+        for (receipt, accounts) in receipts.iter().zip(ctx.remaining_accounts.chunks(3)) {
+            let (request_ai, escrow_ai, user_state_ai) = (&accounts[0], &accounts[1], &accounts[2]);
+            let mut req = Account::<Request>::try_from(request_ai)?;
+            require!(req.is_pending(), ErrorCode::InvalidStatus);
+            let (escrow_key, _) =
+                Pubkey::find_program_address(&[b"escrow", req.key().as_ref()], &crate::ID);
+            require_keys_eq!(escrow_ai.key(), escrow_key, ErrorCode::InvalidBatch);
+            let mut user_state = batch_user_state(user_state_ai, &req)?;
+            let message = build_receipt_message(&req.key(), receipt, req.nonce);
+            let signers = relayer_signers(&ctx.accounts.state, &attestations, &message);
+            require!(!signers.is_empty(), ErrorCode::InvalidSignature);
+            require!(
+                signers.len() >= ctx.accounts.state.required_signatures(),
+                ErrorCode::ThresholdNotMet
+            );
+            let signer = req.approved_signer(&signers)?;
+            confirm_request(
+                &mut ctx.accounts.state,
+                &mut user_state,
+                &mut req,
+                escrow_ai,
+                &mut ctx.accounts.vault,
+                receipt,
+                signer,
+            )?;
+            req.exit(&crate::ID)?;
+            user_state.exit(&crate::ID)?;
+        }
+        Ok(())
+    }
+    /// Confirms one request out of a batch whose receipts the relayers signed
+    /// as a single Merkle root. The leaf is the request's
+    /// `build_receipt_message`, and `merkle_proof` links it to the root signed
+    /// under `build_merkle_root_message`.
+    pub fn verify_receipt_merkle(
+        ctx: Context<UpdateStatus>,
+        receipt: Receipt,
+        merkle_proof: Vec<[u8; 32]>,
+    ) -> Result<()> {
+        require!(merkle_proof.len() <= MAX_MERKLE_DEPTH, ErrorCode::InvalidProof);
+        let req = &mut ctx.accounts.request;
+        require!(req.is_pending(), ErrorCode::InvalidStatus);
+        let leaf = build_receipt_message(&req.key(), &receipt, req.nonce);
+        let message = build_merkle_root_message(&merkle_root(leaf, &merkle_proof));
+        let signers = require_relayer_quorum(
+            &ctx.accounts.state,
+            &ctx.accounts.instruction_sysvar,
+            &message,
+        )?;
+        confirm_request(
+            &mut ctx.accounts.state,
+            &mut ctx.accounts.user_state,
+            req,
+            &ctx.accounts.escrow.to_account_info(),
+            &mut ctx.accounts.vault,
+            &receipt,
+            req.approved_signer(&signers)?,
+        )
+    }
+    /// Points a confirmed request at a migrated or re-pinned blob. Relayers
+    /// sign the new fields under their own domain tag, so a confirmation
+    /// receipt can't be replayed as an update.
+    pub fn update_blob(
+        ctx: Context<UpdateBlob>,
+        blob_id: [u8; 32],
+        sui_tx_hash: [u8; 32],
+        proof_hash: [u8; 32],
+        sui_checkpoint: u64,
+        deadline: i64,
+        sui_object_ids: [[u8; 32]; 2],
+    ) -> Result<()> {
+        let state = &ctx.accounts.state;
+        require!(!state.is_paused(PAUSE_CONFIRM), ErrorCode::Paused);
+        let req = &mut ctx.accounts.request;
+        require!(req.status == Status::Confirmed, ErrorCode::InvalidStatus);
+        require!(!req.challenged, ErrorCode::ChallengePending);
+        let receipt = Receipt {
+            blob_id,
+            sui_tx_hash,
+            proof_hash,
+            sui_checkpoint,
+            deadline,
+            sui_object_ids,
+        };
+        receipt.check(Clock::get()?.unix_timestamp)?;
+        let message = build_blob_update_message(&req.key(), &receipt, req.nonce);
+        require_relayer_quorum(state, &ctx.accounts.instruction_sysvar, &message)?;
+        let old_blob_id = req.blob_id;
         req.blob_id = blob_id;
         req.sui_tx_hash = sui_tx_hash;
         req.proof_hash = proof_hash;
-        req.status = Status::Confirmed;
-        emit!(StorageConfirmed {
-            request_id: ctx.accounts.request.key(),
+        req.sui_checkpoint = sui_checkpoint;
+        req.sui_object_ids = sui_object_ids;
+        req.nonce += 1;
+        emit!(BlobUpdated {
+            request_id: req.key(),
+            old_blob_id,
             blob_id,
             sui_tx_hash,
             proof_hash,
+            sui_checkpoint,
+            sui_object_ids,
         });
         Ok(())
     }
     pub fn mark_failed(ctx: Context<MarkFailed>) -> Result<()> {
+        require!(!ctx.accounts.state.is_paused(PAUSE_FAIL), ErrorCode::Paused);
         let req = &mut ctx.accounts.request;
-        require!(req.status == Status::Pending, ErrorCode::InvalidStatus);
-        req.status = Status::Failed;
-        let vault_ai = ctx.accounts.vault.to_account_info();
-        let user_ai = ctx.accounts.user.to_account_info();
-        **vault_ai.lamports.borrow_mut() -= req.payment;
-        **user_ai.lamports.borrow_mut() += req.payment;
+        require!(req.is_pending(), ErrorCode::InvalidStatus);
+        require!(req.is_native(), ErrorCode::InvalidMint);
+        fail_request(
+            &mut ctx.accounts.state,
+            &mut ctx.accounts.user_state,
+            req,
+            &ctx.accounts.escrow.to_account_info(),
+            &mut ctx.accounts.vault,
+            &ctx.accounts.refund_to.to_account_info(),
+        )
+    }
+    /// Operator escape hatch for a request the relayer can't resolve. It
+    /// ignores the pause flags, like the rest of the admin controls.
+    pub fn admin_force_fail(ctx: Context<ForceFail>) -> Result<()> {
+        let req = &mut ctx.accounts.request;
+        require!(req.is_pending(), ErrorCode::InvalidStatus);
+        require!(req.is_native(), ErrorCode::InvalidMint);
+        fail_request(
+            &mut ctx.accounts.state,
+            &mut ctx.accounts.user_state,
+            req,
+            &ctx.accounts.escrow.to_account_info(),
+            &mut ctx.accounts.vault,
+            &ctx.accounts.refund_to.to_account_info(),
+        )
+    }
+    /// Break-glass override for support. Only moves that leave the escrow and
+    /// vault balances untouched are allowed; anything involving funds has to
+    /// go through the instruction that moves them.
+    pub fn admin_set_status(ctx: Context<AdminSetStatus>, new_status: Status) -> Result<()> {
+        let req = &mut ctx.accounts.request;
+        require!(!req.challenged, ErrorCode::ChallengePending);
+        require!(
+            status_override_allowed(&req.status, &new_status),
+            ErrorCode::InvalidStatusOverride
+        );
+        // Reinstating storage can't extend it past the term that was paid for.
+        if new_status == Status::Confirmed {
+            require!(
+                Clock::get()?.unix_timestamp < req.expiry,
+                ErrorCode::StorageExpired
+            );
+        }
+        let old_status = req.status.clone();
+        // Lifetime counters only track organic transitions, so overrides
+        // don't count twice.
+        transition(req, new_status)?;
+        emit!(StatusOverridden {
+            request_id: req.key(),
+            admin: ctx.accounts.admin.key(),
+            old_status,
+            new_status: req.status.clone(),
+        });
+        Ok(())
+    }
+    /// `remaining_accounts` holds `count` `[request, escrow, refund_to, user_state]`
+    /// groups.
+    /// Requests that are no longer pending, or were paid in tokens, are skipped.
+    pub fn mark_failed_batch<'info>(
+        ctx: Context<'_, '_, 'info, 'info, MarkFailedBatch<'info>>,
+        count: u8,
+    ) -> Result<()> {
+        require!(!ctx.accounts.state.is_paused(PAUSE_FAIL), ErrorCode::Paused);
+        let count = count as usize;
+        require!(
+            count > 0
+                && count <= MAX_BATCH_SIZE
+                && ctx.remaining_accounts.len() == count * 4,
+            ErrorCode::InvalidBatch
+        );
+        for accounts in ctx.remaining_accounts.chunks(4) {
+            let (request_ai, escrow_ai, refund_ai) = (&accounts[0], &accounts[1], &accounts[2]);
+            let mut req = Account::<Request>::try_from(request_ai)?;
+            if !req.is_pending() || !req.is_native() {
+                continue;
+            }
+            let (escrow_key, _) =
+                Pubkey::find_program_address(&[b"escrow", req.key().as_ref()], &crate::ID);
+            require_keys_eq!(escrow_ai.key(), escrow_key, ErrorCode::InvalidBatch);
+            let mut user_state = batch_user_state(&accounts[3], &req)?;
+            fail_request(
+                &mut ctx.accounts.state,
+                &mut user_state,
+                &mut req,
+                escrow_ai,
+                &mut ctx.accounts.vault,
+                refund_ai,
+            )?;
+            req.exit(&crate::ID)?;
+            user_state.exit(&crate::ID)?;
+        }
+        Ok(())
+    }
+    pub fn mark_failed_spl(ctx: Context<MarkFailedSpl>) -> Result<()> {
+        require!(!ctx.accounts.state.is_paused(PAUSE_FAIL), ErrorCode::Paused);
+        let req = &mut ctx.accounts.request;
+        require!(req.is_pending(), ErrorCode::InvalidStatus);
+        transition(req, Status::Failed)?;
+        ctx.accounts.state.record_status(&req.status)?;
+        ctx.accounts.state.record_failure()?;
+        ctx.accounts.state.unlock_tokens(req.payment);
+        ctx.accounts.user_state.close_pending(&mut ctx.accounts.state)?;
+        refund_tokens(
+            &ctx.accounts.token_program,
+            &ctx.accounts.vault_token,
+            &ctx.accounts.user_token,
+            &ctx.accounts.vault,
+            ctx.accounts.state.vault_bump,
+            req.payment,
+        )?;
         emit!(RequestFailed {
-            request_id: req.key()
+            request_id: req.key(),
+            refunded: req.payment,
+            retained: 0,
+        });
+        Ok(())
+    }
+    pub fn timeout_request(ctx: Context<TimeoutRequest>) -> Result<()> {
+        require!(!ctx.accounts.state.is_paused(PAUSE_FAIL), ErrorCode::Paused);
+        let timeout_secs = ctx.accounts.state.timeout_secs;
+        let req = &mut ctx.accounts.request;
+        require!(req.is_pending(), ErrorCode::InvalidStatus);
+        let elapsed = Clock::get()?.unix_timestamp.saturating_sub(req.timestamp);
+        // A zero timeout disables keeper-driven refunds.
+        require!(
+            timeout_secs > 0 && elapsed >= timeout_secs,
+            ErrorCode::TimeoutNotReached
+        );
+        require!(req.is_native(), ErrorCode::InvalidMint);
+        transition(req, Status::Failed)?;
+        ctx.accounts.state.record_status(&req.status)?;
+        ctx.accounts.state.unlock_payment(req.payment)?;
+        ctx.accounts.user_state.close_pending(&mut ctx.accounts.state)?;
+        transfer_lamports(
+            &ctx.accounts.escrow.to_account_info(),
+            &ctx.accounts.refund_to.to_account_info(),
+            req.payment,
+        )?;
+        emit!(RequestTimedOut {
+            request_id: req.key(),
+            elapsed,
+        });
+        Ok(())
+    }
+    pub fn cancel_request(ctx: Context<CancelRequest>) -> Result<()> {
+        let req = &mut ctx.accounts.request;
+        require!(req.is_pending(), ErrorCode::InvalidStatus);
+        require!(req.is_native(), ErrorCode::InvalidMint);
+        transition(req, Status::Cancelled)?;
+        ctx.accounts.state.unlock_payment(req.payment)?;
+        ctx.accounts.user_state.close_pending(&mut ctx.accounts.state)?;
+        transfer_lamports(
+            &ctx.accounts.escrow.to_account_info(),
+            &ctx.accounts.refund_to.to_account_info(),
+            req.payment,
+        )?;
+        emit!(RequestCancelled {
+            request_id: req.key(),
+            refund: req.payment,
+        });
+        Ok(())
+    }
+    pub fn cancel_request_spl(ctx: Context<CancelRequestSpl>) -> Result<()> {
+        let req = &mut ctx.accounts.request;
+        require!(req.is_pending(), ErrorCode::InvalidStatus);
+        transition(req, Status::Cancelled)?;
+        ctx.accounts.state.unlock_tokens(req.payment);
+        ctx.accounts.user_state.close_pending(&mut ctx.accounts.state)?;
+        refund_tokens(
+            &ctx.accounts.token_program,
+            &ctx.accounts.vault_token,
+            &ctx.accounts.user_token,
+            &ctx.accounts.vault,
+            ctx.accounts.state.vault_bump,
+            req.payment,
+        )?;
+        emit!(RequestCancelled {
+            request_id: req.key(),
+            refund: req.payment,
+        });
+        Ok(())
+    }
+    pub fn retry_request(ctx: Context<RetryRequest>, payment_amount: u64) -> Result<()> {
+        let state = &mut ctx.accounts.state;
+        require!(!state.is_paused(PAUSE_INITIATE), ErrorCode::Paused);
+        let req = &mut ctx.accounts.request;
+        require!(req.status == Status::Failed, ErrorCode::InvalidStatus);
+        require!(req.is_native(), ErrorCode::InvalidMint);
+        require!(
+            payment_amount >= state.tier_payment(req.tier, req.data_size)?,
+            ErrorCode::InsufficientPayment
+        );
+        require!(
+            !state.exceeds_max_payment(payment_amount),
+            ErrorCode::PaymentTooLarge
+        );
+        state.lock_payment(payment_amount)?;
+        ctx.accounts.user_state.open_pending(state)?;
+        let duration_secs = req.duration_secs;
+        req.blob_id = [0u8; 32];
+        req.sui_tx_hash = [0u8; 32];
+        req.proof_hash = [0u8; 32];
+        req.sui_checkpoint = 0;
+        req.sui_object_ids = [[0u8; 32]; 2];
+        req.confirmed_by = Pubkey::default();
+        req.payment = payment_amount;
+        req.timestamp = Clock::get()?.unix_timestamp;
+        req.confirm_by = state.confirm_deadline(req.timestamp)?;
+        req.expiry = req
+            .timestamp
+            .checked_add(duration_secs)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        transition(req, Status::Pending)?;
+        deposit(
+            &ctx.accounts.system_program,
+            &ctx.accounts.user,
+            &ctx.accounts.escrow.to_account_info(),
+            payment_amount,
+        )?;
+        emit!(RequestRetried {
+            request_id: req.key(),
+            payment: payment_amount,
+        });
+        Ok(())
+    }
+    pub fn renew_request(
+        ctx: Context<RenewRequest>,
+        additional_secs: i64,
+        payment_amount: u64,
+    ) -> Result<()> {
+        require!(!ctx.accounts.state.is_paused(PAUSE_INITIATE), ErrorCode::Paused);
+        require!(additional_secs > 0, ErrorCode::InvalidDuration);
+        let now = Clock::get()?.unix_timestamp;
+        let req = &mut ctx.accounts.request;
+        require!(req.is_native(), ErrorCode::InvalidMint);
+        require!(!req.challenged, ErrorCode::ChallengePending);
+        match req.status {
+            Status::Expired => {}
+            Status::Confirmed => require!(
+                req.expiry.saturating_sub(now) <= RENEWAL_WINDOW_SECS,
+                ErrorCode::RenewalTooEarly
+            ),
+            _ => return err!(ErrorCode::InvalidStatus),
+        }
+        let state = &mut ctx.accounts.state;
+        let required = state.renewal_payment(req.data_size, req.duration_secs, additional_secs)?;
+        require!(payment_amount >= required, ErrorCode::InsufficientPayment);
+        let fee = bps_of(payment_amount, state.fee_bps);
+        state.accrue_fee(req.key(), fee)?;
+        req.expiry = req
+            .expiry
+            .max(now)
+            .checked_add(additional_secs)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        if req.status == Status::Expired {
+            transition(req, Status::Confirmed)?;
+        }
+        ctx.accounts.vault.record_in(payment_amount)?;
+        deposit(
+            &ctx.accounts.system_program,
+            &ctx.accounts.user,
+            &ctx.accounts.vault.to_account_info(),
+            payment_amount,
+        )?;
+        emit!(RequestRenewed {
+            request_id: req.key(),
+            expiry: req.expiry,
+            payment: payment_amount,
         });
         Ok(())
     }
@@ -141,141 +837,3135 @@ pub mod datahaven_solana {
         let req = &mut ctx.accounts.request;
         require!(req.user == ctx.accounts.user.key(), ErrorCode::Unauthorized);
         require!(req.status == Status::Confirmed, ErrorCode::InvalidStatus);
-        req.status = Status::Revoked;
+        require!(!req.challenged, ErrorCode::ChallengePending);
+        transition(req, Status::Revoked)?;
+        ctx.accounts.state.record_status(&req.status)?;
+        emit!(AccessRevoked {
+            request_id: req.key(),
+            delegate: None,
+        });
+        Ok(())
+    }
+    /// Undoes `revoke_access` while the blob's storage term hasn't ended.
+    pub fn restore_access(ctx: Context<RestoreAccess>) -> Result<()> {
+        let req = &mut ctx.accounts.request;
+        require!(req.status == Status::Revoked, ErrorCode::InvalidStatus);
+        require!(
+            Clock::get()?.unix_timestamp < req.expiry,
+            ErrorCode::StorageExpired
+        );
+        transition(req, Status::Confirmed)?;
+        emit!(AccessRestored {
+            request_id: req.key(),
+        });
+        Ok(())
+    }
+    pub fn grant_access(ctx: Context<ManageDelegates>, delegate: Pubkey) -> Result<()> {
+        add_delegate(&mut ctx.accounts.request, delegate, 0)
+    }
+    /// Like `grant_access`, but the grant lapses at `expires_at` without a
+    /// revoke.
+    pub fn grant_access_until(
+        ctx: Context<ManageDelegates>,
+        delegate: Pubkey,
+        expires_at: i64,
+    ) -> Result<()> {
+        require!(
+            expires_at > Clock::get()?.unix_timestamp,
+            ErrorCode::InvalidExpiry
+        );
+        add_delegate(&mut ctx.accounts.request, delegate, expires_at)
+    }
+    pub fn revoke_delegate(ctx: Context<ManageDelegates>, delegate: Pubkey) -> Result<()> {
+        let req = &mut ctx.accounts.request;
+        let i = req
+            .delegate_index(&delegate)
+            .ok_or(ErrorCode::DelegateNotFound)?;
+        let last = req.delegate_count as usize - 1;
+        req.delegates[i] = req.delegates[last];
+        req.delegates[last] = DelegateGrant::default();
+        req.delegate_count -= 1;
         emit!(AccessRevoked {
-            request_id: req.key()
+            request_id: req.key(),
+            delegate: Some(delegate),
         });
         Ok(())
     }
-    pub fn pause(ctx: Context<AdminAction>) -> Result<()> {
-        ctx.accounts.state.paused = true;
+    pub fn transfer_request(ctx: Context<TransferRequest>, new_owner: Pubkey) -> Result<()> {
+        require!(new_owner != Pubkey::default(), ErrorCode::InvalidOwner);
+        let req = &mut ctx.accounts.request;
+        require!(req.status == Status::Confirmed, ErrorCode::InvalidStatus);
+        require!(!req.challenged, ErrorCode::ChallengePending);
+        let old = req.user;
+        req.user = new_owner;
+        // Delegates and the refund address were chosen by the previous owner.
+        req.delegates = [DelegateGrant::default(); MAX_DELEGATES];
+        req.delegate_count = 0;
+        req.refund_to = Pubkey::default();
+        emit!(OwnershipTransferred {
+            request_id: req.key(),
+            old,
+            new: new_owner,
+        });
         Ok(())
     }
-    pub fn unpause(ctx: Context<AdminAction>) -> Result<()> {
-        ctx.accounts.state.paused = false;
+    /// Corrects a pending native request's metadata. A size change re-prices
+    /// it, charging the owner or refunding the request's refund address the
+    /// difference in the required payment.
+    pub fn update_metadata(
+        ctx: Context<UpdateMetadata>,
+        content_type: [u8; 16],
+        data_size: u64,
+    ) -> Result<()> {
+        require!(!ctx.accounts.state.is_paused(PAUSE_INITIATE), ErrorCode::Paused);
+        require!(content_type != [0u8; 16], ErrorCode::InvalidContentType);
+        let state = &mut ctx.accounts.state;
+        require!(
+            state.accepts_content_type(&content_type),
+            ErrorCode::UnsupportedContentType
+        );
+        let req = &mut ctx.accounts.request;
+        require!(req.is_pending(), ErrorCode::InvalidStatus);
+        require!(req.is_native(), ErrorCode::InvalidMint);
+        require!(!state.exceeds_max_data_size(data_size), ErrorCode::DataTooLarge);
+        let old_required = state.tier_payment(req.tier, req.data_size)?;
+        let new_required = state.tier_payment(req.tier, data_size)?;
+        let escrow_ai = ctx.accounts.escrow.to_account_info();
+        req.content_type = content_type;
+        req.data_size = data_size;
+        if new_required > old_required {
+            let delta = new_required - old_required;
+            req.payment = req
+                .payment
+                .checked_add(delta)
+                .ok_or(ErrorCode::ArithmeticOverflow)?;
+            require!(!state.exceeds_max_payment(req.payment), ErrorCode::PaymentTooLarge);
+            state.lock_payment(delta)?;
+            deposit(&ctx.accounts.system_program, &ctx.accounts.user, &escrow_ai, delta)?;
+        } else if new_required < old_required {
+            let delta = old_required - new_required;
+            req.payment = req
+                .payment
+                .checked_sub(delta)
+                .ok_or(ErrorCode::ArithmeticOverflow)?;
+            state.unlock_payment(delta)?;
+            transfer_lamports(&escrow_ai, &ctx.accounts.refund_to.to_account_info(), delta)?;
+        }
+        emit!(MetadataUpdated {
+            request_id: req.key(),
+            content_type,
+            data_size,
+            payment: req.payment,
+        });
         Ok(())
     }
-    pub fn withdraw(ctx: Context<Withdraw>, amount: u64) -> Result<()> {
+    /// Redirects native refunds of a pending request. The default key sends
+    /// them back to the owner.
+    pub fn set_refund_address(ctx: Context<SetRefundAddress>, refund_to: Pubkey) -> Result<()> {
+        let req = &mut ctx.accounts.request;
+        require!(req.is_pending(), ErrorCode::InvalidStatus);
+        require!(req.is_native(), ErrorCode::InvalidMint);
+        req.refund_to = refund_to;
+        emit!(RefundAddressUpdated {
+            request_id: req.key(),
+            refund_to: req.refund_address(),
+        });
+        Ok(())
+    }
+    /// Relabels a request in any status; tags are only echoed for indexers.
+    pub fn set_tags(ctx: Context<SetTags>, tags: [u8; 4]) -> Result<()> {
+        let req = &mut ctx.accounts.request;
+        req.tags = tags;
+        emit!(TagsUpdated {
+            request_id: req.key(),
+            tags,
+        });
+        Ok(())
+    }
+    /// Makes the relayers re-prove a confirmed native request through one of
+    /// the `answer_challenge*` instructions within `challenge_window_secs`, or
+    /// `fail_challenge` refunds it.
+    pub fn challenge_request(ctx: Context<ChallengeRequest>) -> Result<()> {
+        let window = ctx.accounts.state.challenge_window_secs;
+        require!(window > 0, ErrorCode::ChallengesDisabled);
+        let req = &mut ctx.accounts.request;
+        require!(req.status == Status::Confirmed, ErrorCode::InvalidStatus);
+        require!(!req.challenged, ErrorCode::ChallengePending);
+        require!(req.is_native(), ErrorCode::InvalidMint);
+        // Storage that already ran its term was served in full.
+        let now = Clock::get()?.unix_timestamp;
+        require!(now < req.expiry, ErrorCode::StorageExpired);
+        req.challenged = true;
+        req.challenge_deadline = now
+            .checked_add(window)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        emit!(RequestChallenged {
+            request_id: req.key(),
+            deadline: req.challenge_deadline,
+        });
+        Ok(())
+    }
+    /// Answers a challenge with a fresh receipt signed like `verify_receipt`'s.
+    /// It only touches the request, so it works whoever owns it now.
+    pub fn answer_challenge(ctx: Context<AnswerChallenge>, receipt: Receipt) -> Result<()> {
+        let req = &mut ctx.accounts.request;
+        let message = build_receipt_message(&req.key(), &receipt, req.nonce);
+        let signers = require_relayer_quorum(
+            &ctx.accounts.state,
+            &ctx.accounts.instruction_sysvar,
+            &message,
+        )?;
+        let relayer = req.approved_signer(&signers)?;
+        resolve_challenge(&ctx.accounts.state, req, &receipt, relayer)
+    }
+    /// Like `answer_challenge`, signed like `verify_receipt_secp256k1`.
+    pub fn answer_challenge_secp256k1(
+        ctx: Context<AnswerChallenge>,
+        receipt: Receipt,
+    ) -> Result<()> {
+        let req = &mut ctx.accounts.request;
+        let message = build_receipt_message(&req.key(), &receipt, req.nonce);
+        require_eth_attestation(&ctx.accounts.state, &ctx.accounts.instruction_sysvar, &message)?;
+        let relayer = req.approved_signer(&[ctx.accounts.relayer.key()])?;
+        resolve_challenge(&ctx.accounts.state, req, &receipt, relayer)
+    }
+    /// Like `answer_challenge`, with the receipt under a signed Merkle root
+    /// as in `verify_receipt_merkle`.
+    pub fn answer_challenge_merkle(
+        ctx: Context<AnswerChallenge>,
+        receipt: Receipt,
+        merkle_proof: Vec<[u8; 32]>,
+    ) -> Result<()> {
+        require!(merkle_proof.len() <= MAX_MERKLE_DEPTH, ErrorCode::InvalidProof);
+        let req = &mut ctx.accounts.request;
+        let leaf = build_receipt_message(&req.key(), &receipt, req.nonce);
+        let message = build_merkle_root_message(&merkle_root(leaf, &merkle_proof));
+        let signers = require_relayer_quorum(
+            &ctx.accounts.state,
+            &ctx.accounts.instruction_sysvar,
+            &message,
+        )?;
+        let relayer = req.approved_signer(&signers)?;
+        resolve_challenge(&ctx.accounts.state, req, &receipt, relayer)
+    }
+    /// Permissionlessly fails a challenged request whose deadline passed
+    /// unanswered, refunding its payment from the vault and reversing its fee.
+    pub fn fail_challenge(ctx: Context<FailChallenge>) -> Result<()> {
+        require!(!ctx.accounts.state.is_paused(PAUSE_FAIL), ErrorCode::Paused);
+        let req = &mut ctx.accounts.request;
+        require!(
+            req.status == Status::Confirmed && req.challenged,
+            ErrorCode::InvalidStatus
+        );
+        require!(
+            Clock::get()?.unix_timestamp > req.challenge_deadline,
+            ErrorCode::ChallengeOpen
+        );
+        require!(
+            ctx.accounts.vault.to_account_info().lamports() >= req.payment,
+            ErrorCode::InsufficientVaultBalance
+        );
+        req.challenged = false;
+        transition(req, Status::Failed)?;
+        let state = &mut ctx.accounts.state;
+        state.record_status(&req.status)?;
+        state.accrued_fees = state.accrued_fees.saturating_sub(req.fee_taken);
+        ctx.accounts.vault.record_out(req.payment)?;
         let vault_ai = ctx.accounts.vault.to_account_info();
-        let admin_ai = ctx.accounts.admin.to_account_info();
-        **vault_ai.lamports.borrow_mut() -= amount;
-        **admin_ai.lamports.borrow_mut() += amount;
+        transfer_lamports(&vault_ai, &ctx.accounts.refund_to.to_account_info(), req.payment)?;
+        check_vault_invariant(state, &vault_ai)?;
+        emit!(ChallengeFailed {
+            request_id: req.key(),
+            refunded: req.payment,
+        });
         Ok(())
     }
-}
-
-#[account]
-pub struct State {
-    pub admin: Pubkey,
-    pub relayer: Pubkey,
-    pub paused: bool,
-    pub count: u64,
-}
-#[account]
-pub struct Vault {}
-#[account]
-pub struct Request {
-    pub user: Pubkey,
-    pub data_hash: [u8; 32],
-    pub blob_id: [u8; 32],
-    pub sui_tx_hash: [u8; 32],
-    pub proof_hash: [u8; 32],
-    pub status: Status,
-    pub payment: u64,
-    pub timestamp: i64,
-}
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
-pub enum Status {
-    Pending,
-    Confirmed,
-    Failed,
-    Revoked,
-}
-#[derive(Accounts)]
-pub struct Initialize<'info> {
-    #[account(
-        init,
-        payer = admin,
-        space = 8 + 32 + 32 + 1 + 8,
-        seeds = [b"state"],
-        bump
-    )]
-    pub state: Account<'info, State>,
-    #[account(
-        init,
-        payer = admin,
-        space = 8,
-        seeds = [b"vault"],
-        bump
-    )]
-    pub vault: Account<'info, Vault>,
-    #[account(mut)]
-    pub admin: Signer<'info>,
-    pub system_program: Program<'info, System>,
-}
-#[derive(Accounts)]
-pub struct InitiateStorage<'info> {
-    #[account(mut, seeds = [b"state"], bump)]
-    pub state: Account<'info, State>,
-    #[account(
-        init,
-        payer = user,
-        space = 8 + 32 + 32 + 32 + 32 + 32 + 1 + 8 + 8,
-        seeds = [b"request", user.key().as_ref(), &state.count.to_le_bytes()],
-        bump
-    )]
-    pub request: Account<'info, Request>,
-    #[account(mut)]
-    pub user: Signer<'info>,
-    #[account(mut, seeds = [b"vault"], bump)]
-    pub vault: Account<'info, Vault>,
-    pub system_program: Program<'info, System>,
+    pub fn expire_request(ctx: Context<ExpireRequest>) -> Result<()> {
+        let req = &mut ctx.accounts.request;
+        require!(req.status == Status::Confirmed, ErrorCode::InvalidStatus);
+        require!(!req.challenged, ErrorCode::ChallengePending);
+        require!(
+            Clock::get()?.unix_timestamp >= req.expiry,
+            ErrorCode::NotExpired
+        );
+        transition(req, Status::Expired)?;
+        emit!(RequestExpired {
+            request_id: req.key(),
+            expiry: req.expiry,
+        });
+        Ok(())
+    }
+    pub fn close_request(ctx: Context<CloseRequest>) -> Result<()> {
+        let req = &ctx.accounts.request;
+        require!(
+            matches!(
+                req.status,
+                Status::Failed | Status::Revoked | Status::Expired | Status::Cancelled
+            ),
+            ErrorCode::InvalidStatus
+        );
+        let reclaimed = req
+            .to_account_info()
+            .lamports()
+            .checked_add(ctx.accounts.escrow.to_account_info().lamports())
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        emit!(RequestClosed {
+            request_id: req.key(),
+            reclaimed,
+        });
+        Ok(())
+    }
+    /// Permissionlessly records a confirmed request as the canonical copy of its
+    /// `data_hash`, so later uploads can opt into rejecting duplicates.
+    pub fn register_hash(ctx: Context<RegisterHash>) -> Result<()> {
+        let req = &ctx.accounts.request;
+        ctx.accounts.hash_registry.request = req.key();
+        ctx.accounts.hash_registry.payer = ctx.accounts.payer.key();
+        emit!(HashRegistered {
+            data_hash: req.data_hash,
+            request_id: req.key(),
+        });
+        Ok(())
+    }
+    /// Permissionlessly closes a registry entry whose request is no longer a
+    /// live copy, because it left `Confirmed` or was closed, so the hash can
+    /// be uploaded and registered again. The rent goes back to whoever
+    /// registered it.
+    pub fn release_hash(ctx: Context<ReleaseHash>, data_hash: [u8; 32]) -> Result<()> {
+        let request_ai = ctx.accounts.request.to_account_info();
+        if *request_ai.owner == crate::ID && !request_ai.data_is_empty() {
+            let req = Request::try_deserialize(&mut &request_ai.try_borrow_data()?[..])?;
+            require!(req.status != Status::Confirmed, ErrorCode::InvalidStatus);
+        }
+        emit!(HashReleased {
+            data_hash,
+            request_id: request_ai.key(),
+        });
+        Ok(())
+    }
+    pub fn pause(ctx: Context<PauseAction>) -> Result<()> {
+        let authority = ctx.accounts.authority.key();
+        ctx.accounts.state.write_pause_flags(PAUSE_ALL, authority)?;
+        emit!(Paused {
+            admin: ctx.accounts.authority.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+    pub fn unpause(ctx: Context<AdminAction>) -> Result<()> {
+        let admin = ctx.accounts.admin.key();
+        ctx.accounts.state.write_pause_flags(0, admin)?;
+        emit!(Unpaused {
+            admin: ctx.accounts.admin.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+    pub fn set_pause_flags(ctx: Context<AdminAction>, flags: u8) -> Result<()> {
+        require!(flags & !PAUSE_ALL == 0, ErrorCode::InvalidPauseFlags);
+        let admin = ctx.accounts.admin.key();
+        ctx.accounts.state.write_pause_flags(flags, admin)
+    }
+    pub fn set_guardian(ctx: Context<AdminAction>, new_guardian: Pubkey) -> Result<()> {
+        let state = &mut ctx.accounts.state;
+        let old_guardian = state.guardian;
+        state.guardian = new_guardian;
+        emit!(GuardianUpdated {
+            old_guardian,
+            new_guardian,
+        });
+        Ok(())
+    }
+    /// Routes withdrawals to `new_recipient`, e.g. a treasury multisig. The
+    /// default key sends them back to the admin. The change is queued behind
+    /// the withdraw delay and applied with `apply_fee_recipient`.
+    pub fn set_fee_recipient(ctx: Context<AdminAction>, new_recipient: Pubkey) -> Result<()> {
+        let state = &mut ctx.accounts.state;
+        if state.withdraw_delay_secs == 0 {
+            state.fee_recipient_unlock_time = 0;
+            state.write_fee_recipient(new_recipient);
+            return Ok(());
+        }
+        state.pending_fee_recipient = new_recipient;
+        state.fee_recipient_unlock_time = Clock::get()?
+            .unix_timestamp
+            .checked_add(state.withdraw_delay_secs)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        emit!(FeeRecipientQueued {
+            new_recipient,
+            unlock_time: state.fee_recipient_unlock_time,
+        });
+        Ok(())
+    }
+    pub fn apply_fee_recipient(ctx: Context<AdminAction>) -> Result<()> {
+        let state = &mut ctx.accounts.state;
+        require!(state.fee_recipient_unlock_time != 0, ErrorCode::ChangeNotQueued);
+        require!(
+            Clock::get()?.unix_timestamp >= state.fee_recipient_unlock_time,
+            ErrorCode::ChangeLocked
+        );
+        state.fee_recipient_unlock_time = 0;
+        let new_recipient = state.pending_fee_recipient;
+        state.write_fee_recipient(new_recipient);
+        Ok(())
+    }
+    pub fn propose_admin(ctx: Context<AdminAction>, new_admin: Pubkey) -> Result<()> {
+        require!(new_admin != Pubkey::default(), ErrorCode::InvalidAdmin);
+        ctx.accounts.state.pending_admin = new_admin;
+        Ok(())
+    }
+    pub fn accept_admin(ctx: Context<AcceptAdmin>) -> Result<()> {
+        let state = &mut ctx.accounts.state;
+        let old = state.admin;
+        state.admin = ctx.accounts.new_admin.key();
+        state.pending_admin = Pubkey::default();
+        emit!(AdminTransferred {
+            old,
+            new: state.admin,
+        });
+        Ok(())
+    }
+    pub fn clear_pending_admin(ctx: Context<AdminAction>) -> Result<()> {
+        ctx.accounts.state.pending_admin = Pubkey::default();
+        Ok(())
+    }
+    /// Like `add_relayer`, the new relayer co-signs to bring its bond up to
+    /// `relayer_bond`; a bond left from an earlier enrollment is reused.
+    pub fn set_relayer(ctx: Context<SetRelayer>, new_relayer: Pubkey) -> Result<()> {
+        require!(new_relayer != Pubkey::default(), ErrorCode::InvalidRelayer);
+        let state = &mut ctx.accounts.state;
+        require!(!state.is_relayer(&new_relayer), ErrorCode::DuplicateRelayer);
+        let old_relayer = state.relayer;
+        // The primary relayer always occupies a slot in the allowlist.
+        match state.relayer_index(&old_relayer) {
+            Some(i) => state.relayers[i] = new_relayer,
+            None => state.push_relayer(new_relayer)?,
+        }
+        state.relayer = new_relayer;
+        post_bond(
+            &mut ctx.accounts.bond,
+            new_relayer,
+            state.relayer_bond,
+            &ctx.accounts.system_program,
+            &ctx.accounts.relayer_signer,
+        )?;
+        emit!(RelayerUpdated {
+            old_relayer,
+            new_relayer,
+        });
+        Ok(())
+    }
+    /// The relayer co-signs to post `relayer_bond` into its bond PDA.
+    pub fn add_relayer(ctx: Context<AddRelayer>, relayer: Pubkey) -> Result<()> {
+        require!(relayer != Pubkey::default(), ErrorCode::InvalidRelayer);
+        let state = &mut ctx.accounts.state;
+        require!(!state.is_relayer(&relayer), ErrorCode::DuplicateRelayer);
+        state.push_relayer(relayer)?;
+        post_bond(
+            &mut ctx.accounts.bond,
+            relayer,
+            state.relayer_bond,
+            &ctx.accounts.system_program,
+            &ctx.accounts.relayer_signer,
+        )?;
+        emit!(RelayerAdded { relayer });
+        Ok(())
+    }
+    /// Confiscates `amount` of a relayer's bond into the vault, e.g. after it
+    /// confirmed an invalid receipt.
+    pub fn slash_relayer(ctx: Context<SlashRelayer>, relayer: Pubkey, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        let bond = &mut ctx.accounts.bond;
+        bond.amount = bond
+            .amount
+            .checked_sub(amount)
+            .ok_or(ErrorCode::InsufficientBond)?;
+        ctx.accounts.vault.record_in(amount)?;
+        transfer_lamports(
+            &bond.to_account_info(),
+            &ctx.accounts.vault.to_account_info(),
+            amount,
+        )?;
+        emit!(RelayerSlashed {
+            relayer,
+            amount,
+            remaining: bond.amount,
+        });
+        Ok(())
+    }
+    /// Returns what's left of the bond, plus its rent, once the relayer has
+    /// been removed.
+    pub fn withdraw_bond(ctx: Context<WithdrawBond>) -> Result<()> {
+        let relayer = ctx.accounts.relayer.key();
+        require!(!ctx.accounts.state.is_relayer(&relayer), ErrorCode::RelayerStillActive);
+        emit!(BondWithdrawn {
+            relayer,
+            amount: ctx.accounts.bond.amount,
+        });
+        Ok(())
+    }
+    pub fn set_max_data_size(ctx: Context<AdminAction>, max_data_size: u64) -> Result<()> {
+        ctx.accounts.state.max_data_size = max_data_size;
+        emit!(MaxDataSizeUpdated { max_data_size });
+        Ok(())
+    }
+    pub fn set_min_request_interval(
+        ctx: Context<AdminAction>,
+        min_request_interval: i64,
+    ) -> Result<()> {
+        require!(min_request_interval >= 0, ErrorCode::InvalidDuration);
+        ctx.accounts.state.min_request_interval = min_request_interval;
+        emit!(MinRequestIntervalUpdated {
+            min_request_interval,
+        });
+        Ok(())
+    }
+    /// Replaces the content-type allowlist. All-zero slots are ignored, and
+    /// an all-zero list accepts anything.
+    pub fn set_allowed_content_types(
+        ctx: Context<AdminAction>,
+        content_types: [[u8; 16]; MAX_CONTENT_TYPES],
+    ) -> Result<()> {
+        ctx.accounts.state.allowed_content_types = content_types;
+        emit!(AllowedContentTypesUpdated { content_types });
+        Ok(())
+    }
+    pub fn set_queue_when_paused(ctx: Context<AdminAction>, enabled: bool) -> Result<()> {
+        ctx.accounts.state.queue_when_paused = enabled;
+        emit!(QueueModeUpdated { enabled });
+        Ok(())
+    }
+    pub fn set_relayer_bond(ctx: Context<AdminAction>, relayer_bond: u64) -> Result<()> {
+        ctx.accounts.state.relayer_bond = relayer_bond;
+        emit!(RelayerBondUpdated { relayer_bond });
+        Ok(())
+    }
+    pub fn remove_relayer(ctx: Context<AdminAction>, relayer: Pubkey) -> Result<()> {
+        let state = &mut ctx.accounts.state;
+        // Rotate the primary away with set_relayer before removing it.
+        require!(relayer != state.relayer, ErrorCode::InvalidRelayer);
+        let i = state
+            .relayer_index(&relayer)
+            .ok_or(ErrorCode::RelayerNotFound)?;
+        require!(
+            state.relayer_count as usize > state.required_signatures(),
+            ErrorCode::InvalidThreshold
+        );
+        let last = state.relayer_count as usize - 1;
+        state.relayers[i] = state.relayers[last];
+        state.relayers[last] = Pubkey::default();
+        state.relayer_count -= 1;
+        emit!(RelayerRemoved { relayer });
+        Ok(())
+    }
+    pub fn set_min_payment(ctx: Context<AdminAction>, new_min: u64) -> Result<()> {
+        set_payment_bounds(&mut ctx.accounts.state, Some(new_min), None)
+    }
+    pub fn set_max_payment(ctx: Context<AdminAction>, new_max: u64) -> Result<()> {
+        set_payment_bounds(&mut ctx.accounts.state, None, Some(new_max))
+    }
+    pub fn set_creation_fee(ctx: Context<AdminAction>, creation_fee: u64) -> Result<()> {
+        ctx.accounts.state.creation_fee = creation_fee;
+        emit!(CreationFeeUpdated { creation_fee });
+        Ok(())
+    }
+    pub fn set_pricing(ctx: Context<AdminAction>, base_fee: u64, per_byte_fee: u64) -> Result<()> {
+        let state = &mut ctx.accounts.state;
+        state.base_fee = base_fee;
+        state.per_byte_fee = per_byte_fee;
+        emit!(PricingUpdated {
+            base_fee,
+            per_byte_fee,
+        });
+        Ok(())
+    }
+    pub fn set_tier_config(
+        ctx: Context<AdminAction>,
+        tier: u8,
+        min_payment: u64,
+        default_duration_secs: i64,
+    ) -> Result<()> {
+        require!((tier as usize) < MAX_TIERS, ErrorCode::InvalidTier);
+        require!(default_duration_secs >= 0, ErrorCode::InvalidDuration);
+        ctx.accounts.state.tiers[tier as usize] = TierConfig {
+            min_payment,
+            default_duration_secs,
+        };
+        emit!(TierConfigUpdated {
+            tier,
+            min_payment,
+            default_duration_secs,
+        });
+        Ok(())
+    }
+    pub fn set_timeout(ctx: Context<AdminAction>, timeout_secs: i64) -> Result<()> {
+        require!(timeout_secs >= 0, ErrorCode::InvalidDuration);
+        ctx.accounts.state.timeout_secs = timeout_secs;
+        emit!(TimeoutUpdated { timeout_secs });
+        Ok(())
+    }
+    pub fn set_max_pending_per_user(
+        ctx: Context<AdminAction>,
+        max_pending_per_user: u16,
+    ) -> Result<()> {
+        ctx.accounts.state.max_pending_per_user = max_pending_per_user;
+        emit!(MaxPendingUpdated {
+            max_pending_per_user
+        });
+        Ok(())
+    }
+    /// How long relayers get to answer a challenge. Zero disables challenges.
+    pub fn set_challenge_window(
+        ctx: Context<AdminAction>,
+        challenge_window_secs: i64,
+    ) -> Result<()> {
+        require!(challenge_window_secs >= 0, ErrorCode::InvalidDuration);
+        ctx.accounts.state.challenge_window_secs = challenge_window_secs;
+        emit!(ChallengeWindowUpdated {
+            challenge_window_secs
+        });
+        Ok(())
+    }
+    pub fn set_confirm_window(ctx: Context<AdminAction>, confirm_window_secs: i64) -> Result<()> {
+        require!(confirm_window_secs >= 0, ErrorCode::InvalidDuration);
+        ctx.accounts.state.confirm_window_secs = confirm_window_secs;
+        emit!(ConfirmWindowUpdated {
+            confirm_window_secs
+        });
+        Ok(())
+    }
+    pub fn set_threshold(ctx: Context<AdminAction>, threshold: u8) -> Result<()> {
+        let state = &mut ctx.accounts.state;
+        require!(
+            threshold >= 1 && threshold <= state.relayer_count,
+            ErrorCode::InvalidThreshold
+        );
+        state.threshold = threshold;
+        emit!(ThresholdUpdated { threshold });
+        Ok(())
+    }
+    pub fn set_relayer_eth_address(
+        ctx: Context<AdminAction>,
+        eth_address: [u8; 20],
+    ) -> Result<()> {
+        ctx.accounts.state.relayer_eth_address = eth_address;
+        emit!(RelayerEthAddressUpdated { eth_address });
+        Ok(())
+    }
+    pub fn set_payment_mint(ctx: Context<SetPaymentMint>) -> Result<()> {
+        let mint = ctx.accounts.mint.key();
+        ctx.accounts.state.payment_mint = mint;
+        emit!(PaymentMintUpdated { mint });
+        Ok(())
+    }
+    pub fn set_fee_bps(ctx: Context<AdminAction>, fee_bps: u16) -> Result<()> {
+        require!(fee_bps <= MAX_BPS, ErrorCode::InvalidFeeBps);
+        ctx.accounts.state.fee_bps = fee_bps;
+        emit!(FeeUpdated { fee_bps });
+        Ok(())
+    }
+    pub fn set_failure_fee_bps(ctx: Context<AdminAction>, failure_fee_bps: u16) -> Result<()> {
+        require!(failure_fee_bps <= MAX_BPS, ErrorCode::InvalidFeeBps);
+        ctx.accounts.state.failure_fee_bps = failure_fee_bps;
+        emit!(FailureFeeUpdated { failure_fee_bps });
+        Ok(())
+    }
+    /// Auto-pauses everything once `threshold` requests fail within
+    /// `window_secs`. A zero threshold disables the breaker.
+    pub fn set_breaker_threshold(
+        ctx: Context<AdminAction>,
+        threshold: u16,
+        window_secs: i64,
+    ) -> Result<()> {
+        require!(window_secs > 0 || threshold == 0, ErrorCode::InvalidDuration);
+        let state = &mut ctx.accounts.state;
+        state.breaker_threshold = threshold;
+        state.breaker_window_secs = window_secs;
+        state.breaker_failures = 0;
+        emit!(BreakerThresholdUpdated {
+            threshold,
+            window_secs,
+        });
+        Ok(())
+    }
+    /// Raising the delay takes effect at once. Lowering it is queued behind
+    /// the delay in force and applied with `apply_withdraw_delay`, so a
+    /// shorter delay can't be used to skip the current one.
+    pub fn set_withdraw_delay(ctx: Context<AdminAction>, delay_secs: i64) -> Result<()> {
+        require!(delay_secs >= 0, ErrorCode::InvalidDuration);
+        let state = &mut ctx.accounts.state;
+        if delay_secs >= state.withdraw_delay_secs {
+            state.withdraw_delay_secs = delay_secs;
+            state.withdraw_delay_unlock_time = 0;
+            emit!(WithdrawDelayUpdated { delay_secs });
+            return Ok(());
+        }
+        state.pending_withdraw_delay_secs = delay_secs;
+        state.withdraw_delay_unlock_time = Clock::get()?
+            .unix_timestamp
+            .checked_add(state.withdraw_delay_secs)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        emit!(WithdrawDelayQueued {
+            delay_secs,
+            unlock_time: state.withdraw_delay_unlock_time,
+        });
+        Ok(())
+    }
+    pub fn apply_withdraw_delay(ctx: Context<AdminAction>) -> Result<()> {
+        let state = &mut ctx.accounts.state;
+        require!(state.withdraw_delay_unlock_time != 0, ErrorCode::ChangeNotQueued);
+        require!(
+            Clock::get()?.unix_timestamp >= state.withdraw_delay_unlock_time,
+            ErrorCode::ChangeLocked
+        );
+        state.withdraw_delay_unlock_time = 0;
+        state.withdraw_delay_secs = state.pending_withdraw_delay_secs;
+        emit!(WithdrawDelayUpdated {
+            delay_secs: state.withdraw_delay_secs,
+        });
+        Ok(())
+    }
+    pub fn queue_withdraw(ctx: Context<AdminAction>, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        let state = &mut ctx.accounts.state;
+        state.pending_withdraw_amount = amount;
+        state.withdraw_unlock_time = Clock::get()?
+            .unix_timestamp
+            .checked_add(state.withdraw_delay_secs)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        emit!(WithdrawQueued {
+            amount,
+            unlock_time: state.withdraw_unlock_time,
+        });
+        Ok(())
+    }
+    /// Token-revenue counterpart of `queue_withdraw`, behind the same delay.
+    pub fn queue_token_withdraw(ctx: Context<AdminAction>, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        let state = &mut ctx.accounts.state;
+        state.pending_token_withdraw_amount = amount;
+        state.token_withdraw_unlock_time = Clock::get()?
+            .unix_timestamp
+            .checked_add(state.withdraw_delay_secs)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        emit!(TokenWithdrawQueued {
+            amount,
+            unlock_time: state.token_withdraw_unlock_time,
+        });
+        Ok(())
+    }
+    /// Pays a queued amount of confirmed token revenue out of a vault token
+    /// account. Token payments of pending requests (`locked_tokens`) stay put
+    /// so they can still be refunded.
+    pub fn withdraw_tokens(ctx: Context<WithdrawTokens>, amount: u64) -> Result<()> {
+        require!(!ctx.accounts.state.is_paused(PAUSE_WITHDRAW), ErrorCode::Paused);
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        let state = &mut ctx.accounts.state;
+        require!(
+            state.pending_token_withdraw_amount > 0
+                && amount == state.pending_token_withdraw_amount,
+            ErrorCode::WithdrawNotQueued
+        );
+        require!(
+            Clock::get()?.unix_timestamp >= state.token_withdraw_unlock_time,
+            ErrorCode::WithdrawLocked
+        );
+        let available = ctx
+            .accounts
+            .vault_token
+            .amount
+            .saturating_sub(state.locked_tokens);
+        require!(amount <= available, ErrorCode::InsufficientVaultBalance);
+        state.pending_token_withdraw_amount = 0;
+        refund_tokens(
+            &ctx.accounts.token_program,
+            &ctx.accounts.vault_token,
+            &ctx.accounts.recipient_token,
+            &ctx.accounts.vault,
+            state.vault_bump,
+            amount,
+        )?;
+        emit!(TokensWithdrawn {
+            admin: ctx.accounts.admin.key(),
+            recipient: ctx.accounts.recipient_token.key(),
+            mint: ctx.accounts.vault_token.mint,
+            amount,
+        });
+        Ok(())
+    }
+    pub fn withdraw(ctx: Context<Withdraw>, amount: u64) -> Result<()> {
+        require!(!ctx.accounts.state.is_paused(PAUSE_WITHDRAW), ErrorCode::Paused);
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        let state = &mut ctx.accounts.state;
+        require!(
+            state.pending_withdraw_amount > 0 && amount == state.pending_withdraw_amount,
+            ErrorCode::WithdrawNotQueued
+        );
+        require!(
+            Clock::get()?.unix_timestamp >= state.withdraw_unlock_time,
+            ErrorCode::WithdrawLocked
+        );
+        state.pending_withdraw_amount = 0;
+        let vault_ai = ctx.accounts.vault.to_account_info();
+        let recipient_ai = ctx.accounts.recipient.to_account_info();
+        // Accrued fees are only withdrawable through withdraw_fees. Pending
+        // payments (`locked_lamports`) never reach the vault: they stay in their
+        // request's escrow until confirmed, so refunds can't be drained here.
+        let min_balance = Rent::get()?
+            .minimum_balance(vault_ai.data_len())
+            .checked_add(state.accrued_fees)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        let remaining = vault_ai
+            .lamports()
+            .checked_sub(amount)
+            .ok_or(ErrorCode::InsufficientVaultBalance)?;
+        require!(remaining >= min_balance, ErrorCode::InsufficientVaultBalance);
+        ctx.accounts.vault.record_out(amount)?;
+        transfer_lamports(&vault_ai, &recipient_ai, amount)?;
+        check_vault_invariant(state, &vault_ai)?;
+        emit!(Withdrawn {
+            admin: ctx.accounts.admin.key(),
+            recipient: recipient_ai.key(),
+            amount,
+            vault_balance_after: vault_ai.lamports(),
+        });
+        Ok(())
+    }
+    pub fn withdraw_fees(ctx: Context<Withdraw>, amount: u64) -> Result<()> {
+        require!(!ctx.accounts.state.is_paused(PAUSE_WITHDRAW), ErrorCode::Paused);
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        let state = &mut ctx.accounts.state;
+        state.accrued_fees = state
+            .accrued_fees
+            .checked_sub(amount)
+            .ok_or(ErrorCode::InsufficientFees)?;
+        let vault_ai = ctx.accounts.vault.to_account_info();
+        let min_balance = Rent::get()?.minimum_balance(vault_ai.data_len());
+        let remaining = vault_ai
+            .lamports()
+            .checked_sub(amount)
+            .ok_or(ErrorCode::InsufficientVaultBalance)?;
+        require!(remaining >= min_balance, ErrorCode::InsufficientVaultBalance);
+        ctx.accounts.vault.record_out(amount)?;
+        transfer_lamports(&vault_ai, &ctx.accounts.recipient.to_account_info(), amount)?;
+        check_vault_invariant(state, &vault_ai)?;
+        emit!(FeesWithdrawn {
+            admin: ctx.accounts.admin.key(),
+            recipient: ctx.accounts.recipient.key(),
+            amount,
+            accrued_fees_after: state.accrued_fees,
+            vault_balance_after: vault_ai.lamports(),
+        });
+        Ok(())
+    }
+    /// Pays out all accrued fees, plus the queued storage-revenue withdrawal if
+    /// its timelock has passed, in one transfer to the payout address.
+    pub fn withdraw_all(ctx: Context<Withdraw>) -> Result<()> {
+        require!(!ctx.accounts.state.is_paused(PAUSE_WITHDRAW), ErrorCode::Paused);
+        let state = &mut ctx.accounts.state;
+        let fees = state.accrued_fees;
+        let unlocked = Clock::get()?.unix_timestamp >= state.withdraw_unlock_time;
+        let revenue = if unlocked {
+            state.pending_withdraw_amount
+        } else {
+            0
+        };
+        let amount = fees
+            .checked_add(revenue)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        let vault_ai = ctx.accounts.vault.to_account_info();
+        let min_balance = Rent::get()?.minimum_balance(vault_ai.data_len());
+        let remaining = vault_ai
+            .lamports()
+            .checked_sub(amount)
+            .ok_or(ErrorCode::InsufficientVaultBalance)?;
+        require!(remaining >= min_balance, ErrorCode::InsufficientVaultBalance);
+        state.accrued_fees = 0;
+        if revenue > 0 {
+            state.pending_withdraw_amount = 0;
+        }
+        ctx.accounts.vault.record_out(amount)?;
+        let recipient_ai = ctx.accounts.recipient.to_account_info();
+        transfer_lamports(&vault_ai, &recipient_ai, amount)?;
+        check_vault_invariant(state, &vault_ai)?;
+        let admin = ctx.accounts.admin.key();
+        if revenue > 0 {
+            emit!(Withdrawn {
+                admin,
+                recipient: recipient_ai.key(),
+                amount: revenue,
+                vault_balance_after: vault_ai.lamports(),
+            });
+        }
+        if fees > 0 {
+            emit!(FeesWithdrawn {
+                admin,
+                recipient: recipient_ai.key(),
+                amount: fees,
+                accrued_fees_after: 0,
+                vault_balance_after: vault_ai.lamports(),
+            });
+        }
+        Ok(())
+    }
+    /// Evacuates everything above rent, accrued fees included, bypassing the
+    /// withdraw timelock. Only allowed while new requests are paused, and only
+    /// to the recipient in force when they were; pending payments are
+    /// untouched in their escrows.
+    pub fn emergency_withdraw(ctx: Context<Withdraw>) -> Result<()> {
+        let state = &mut ctx.accounts.state;
+        require!(state.is_paused(PAUSE_INITIATE), ErrorCode::NotPaused);
+        require_keys_eq!(
+            ctx.accounts.recipient.key(),
+            state.emergency_recipient,
+            ErrorCode::InvalidRecipient
+        );
+        let vault_ai = ctx.accounts.vault.to_account_info();
+        let min_balance = Rent::get()?.minimum_balance(vault_ai.data_len());
+        let amount = vault_ai.lamports().saturating_sub(min_balance);
+        require!(amount > 0, ErrorCode::InsufficientVaultBalance);
+        state.accrued_fees = 0;
+        state.pending_withdraw_amount = 0;
+        ctx.accounts.vault.record_out(amount)?;
+        transfer_lamports(&vault_ai, &ctx.accounts.recipient.to_account_info(), amount)?;
+        emit!(EmergencyWithdraw {
+            admin: ctx.accounts.admin.key(),
+            recipient: ctx.accounts.recipient.key(),
+            amount,
+        });
+        Ok(())
+    }
+    /// Recovers lamports stranded in a program-owned account that isn't one of
+    /// the program's live account types, crediting them to the vault. Closed
+    /// requests and escrows go back to the System Program, so their dust is
+    /// swept with `admin_sweep_request` instead.
+    pub fn admin_sweep(ctx: Context<AdminSweep>, _target: Pubkey) -> Result<()> {
+        let target_ai = ctx.accounts.target_account.to_account_info();
+        require_keys_eq!(*target_ai.owner, crate::ID, ErrorCode::InvalidSweepTarget);
+        require!(
+            !is_live_account(&target_ai.try_borrow_data()?),
+            ErrorCode::InvalidSweepTarget
+        );
+        let amount = target_ai.lamports();
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        ctx.accounts.vault.record_in(amount)?;
+        transfer_lamports(&target_ai, &ctx.accounts.vault.to_account_info(), amount)?;
+        emit!(DustSwept {
+            target: target_ai.key(),
+            amount,
+        });
+        Ok(())
+    }
+    /// Recovers lamports sent to the request PDA `user`/`index` or its escrow
+    /// after `close_request` reaped them, crediting them to the vault. Only
+    /// indices the user has already opened, at addresses the System Program
+    /// owns with no data, are touched, so live requests and ones not opened
+    /// yet are left alone.
+    pub fn admin_sweep_request(
+        ctx: Context<AdminSweepRequest>,
+        user: Pubkey,
+        index: u64,
+    ) -> Result<()> {
+        require!(
+            index < ctx.accounts.user_state.request_count,
+            ErrorCode::InvalidSweepTarget
+        );
+        let request_ai = ctx.accounts.request.to_account_info();
+        let escrow_ai = ctx.accounts.escrow.to_account_info();
+        let request_amount = reaped_lamports(&request_ai);
+        let escrow_amount = reaped_lamports(&escrow_ai);
+        let amount = request_amount
+            .checked_add(escrow_amount)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        ctx.accounts.vault.record_in(amount)?;
+        let vault_ai = ctx.accounts.vault.to_account_info();
+        let index = index.to_le_bytes();
+        let request_key = request_ai.key();
+        for (account, amount, seeds) in [
+            (
+                &request_ai,
+                request_amount,
+                &[b"request".as_ref(), user.as_ref(), &index, &[ctx.bumps.request]][..],
+            ),
+            (
+                &escrow_ai,
+                escrow_amount,
+                &[b"escrow".as_ref(), request_key.as_ref(), &[ctx.bumps.escrow]][..],
+            ),
+        ] {
+            if amount == 0 {
+                continue;
+            }
+            system_program::transfer(
+                CpiContext::new_with_signer(
+                    ctx.accounts.system_program.to_account_info(),
+                    system_program::Transfer {
+                        from: account.clone(),
+                        to: vault_ai.clone(),
+                    },
+                    &[seeds],
+                ),
+                amount,
+            )?;
+            emit!(DustSwept {
+                target: account.key(),
+                amount,
+            });
+        }
+        Ok(())
+    }
+    /// The minimum `payment_amount` `initiate_storage` accepts for `data_size`
+    /// bytes at `tier`. `creation_fee` is charged on top.
+    pub fn quote_payment(ctx: Context<StateView>, data_size: u64, tier: u8) -> Result<u64> {
+        ctx.accounts.state.tier_payment(tier, data_size)
+    }
+    /// Whether `candidate` is in the active relayer set.
+    pub fn is_relayer(ctx: Context<StateView>, candidate: Pubkey) -> Result<bool> {
+        Ok(ctx.accounts.state.is_relayer(&candidate))
+    }
+    pub fn check_access(ctx: Context<RequestView>, requester: Pubkey) -> Result<bool> {
+        let now = Clock::get()?.unix_timestamp;
+        Ok(ctx.accounts.request.can_access(&requester, now))
+    }
+    pub fn get_vault_summary(ctx: Context<VaultView>) -> Result<VaultSummary> {
+        let state = &ctx.accounts.state;
+        let vault_ai = ctx.accounts.vault.to_account_info();
+        let total_lamports = vault_ai.lamports();
+        let reserved = Rent::get()?
+            .minimum_balance(vault_ai.data_len())
+            .saturating_add(state.accrued_fees);
+        Ok(VaultSummary {
+            total_lamports,
+            accrued_fees: state.accrued_fees,
+            locked_in_pending: state.locked_lamports,
+            withdrawable: total_lamports.saturating_sub(reserved),
+        })
+    }
+    /// One-call summary for monitoring, meant to be simulated.
+    pub fn get_health(ctx: Context<VaultView>) -> Result<HealthStatus> {
+        let state = &ctx.accounts.state;
+        Ok(HealthStatus {
+            pause_flags: state.pause_flags,
+            relayer_count: state.relayer_count,
+            threshold: state.threshold,
+            pending_count: state.pending_count,
+            vault_lamports: ctx.accounts.vault.get_lamports(),
+            version: state.version,
+        })
+    }
+    /// How many requests `user` has opened. Their request PDAs are
+    /// `[b"request", user, index.to_le_bytes()]` for every `index` below this
+    /// count (closed ones no longer exist).
+    pub fn get_user_request_count(ctx: Context<UserView>, _user: Pubkey) -> Result<u64> {
+        let user_state_ai = ctx.accounts.user_state.to_account_info();
+        // Users who never opened a request have no `UserState` yet.
+        if user_state_ai.data_is_empty() {
+            return Ok(0);
+        }
+        let user_state = UserState::try_deserialize(&mut &user_state_ai.try_borrow_data()?[..])?;
+        Ok(user_state.request_count)
+    }
+    /// The request PDA `user` gets for their `index`-th request, so clients
+    /// can show it before submitting. The next one is at
+    /// `get_user_request_count`.
+    pub fn derive_request_id(_ctx: Context<StateView>, user: Pubkey, index: u64) -> Result<Pubkey> {
+        Ok(request_address(&user, index).0)
+    }
+}
+
+/// The request PDA and bump for `user`'s `index`-th request:
+/// `[b"request", user, index.to_le_bytes()]`, as the initiate instructions
+/// derive it from `UserState::request_count`.
+pub fn request_address(user: &Pubkey, index: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"request", user.as_ref(), &index.to_le_bytes()], &crate::ID)
+}
+
+/// The 32-byte message relayers sign to confirm `request`, as checked by the
+/// `verify_receipt*` instructions. Off-chain relayers can link this crate to
+/// build the exact same preimage:
+/// `keccak(domain || program_id || request || blob_id || sui_tx_hash ||
+/// proof_hash || sui_checkpoint || nonce || deadline || sui_object_ids)`,
+/// integers little-endian, with `nonce` the request's current nonce.
+pub fn build_receipt_message(request: &Pubkey, receipt: &Receipt, nonce: u64) -> [u8; 32] {
+    receipt_message(RECEIPT_DOMAIN, request, receipt, nonce)
+}
+
+/// Like `build_receipt_message`, for `update_blob`.
+pub fn build_blob_update_message(request: &Pubkey, receipt: &Receipt, nonce: u64) -> [u8; 32] {
+    receipt_message(BLOB_UPDATE_DOMAIN, request, receipt, nonce)
+}
+
+/// Folds `proof` into `leaf`, hashing each pair in sorted order so proofs
+/// don't need to encode left/right positions.
+pub fn merkle_root(leaf: [u8; 32], proof: &[[u8; 32]]) -> [u8; 32] {
+    proof.iter().fold(leaf, |node, sibling| {
+        let (a, b) = if node <= *sibling {
+            (node, *sibling)
+        } else {
+            (*sibling, node)
+        };
+        keccak::hashv(&[&a, &b]).0
+    })
+}
+
+/// The message relayers sign to vouch for every receipt under `root`.
+pub fn build_merkle_root_message(root: &[u8; 32]) -> [u8; 32] {
+    keccak::hashv(&[MERKLE_ROOT_DOMAIN, crate::ID.as_ref(), root]).0
+}
+
+/// The message a relayer signs to attest that a request's blob is stored.
+/// The domain tag names the action, and the program id scopes the signature
+/// to this deployment.
+fn receipt_message(domain: &[u8], request: &Pubkey, receipt: &Receipt, nonce: u64) -> [u8; 32] {
+    keccak::hashv(&[
+        domain,
+        crate::ID.as_ref(),
+        request.as_ref(),
+        &receipt.blob_id,
+        &receipt.sui_tx_hash,
+        &receipt.proof_hash,
+        &receipt.sui_checkpoint.to_le_bytes(),
+        &nonce.to_le_bytes(),
+        &receipt.deadline.to_le_bytes(),
+        &receipt.sui_object_ids[0],
+        &receipt.sui_object_ids[1],
+    ])
+    .0
+}
+
+/// Parses the Ed25519 verify instruction at `index` into the public key and
+/// 32-byte message it verified. Multi-signature instructions, and ones that
+/// read a field from another instruction, are ignored.
+fn ed25519_attestation(ix: &Instruction, index: usize) -> Option<(Pubkey, [u8; 32])> {
+    if ix.program_id != ed25519_program::ID {
+        return None;
+    }
+
+    // Ed25519 instruction data format:
+    // [0]: number of signatures (u8)
+    // [1]: padding (u8)
+    // [2..4]: signature offset (u16)
+    // [4..6]: signature instruction index (u16)
+    // [6..8]: public key offset (u16)
+    // [8..10]: public key instruction index (u16)
+    // [10..12]: message data offset (u16)
+    // [12..14]: message data size (u16)
+    // [14..16]: message instruction index (u16)
+    let header = ix.data.get(..16)?;
+    let field = |at: usize| u16::from_le_bytes([header[at], header[at + 1]]);
+    // Instruction indices must be u16::MAX or this instruction's own index.
+    let local = |at: usize| field(at) == u16::MAX || field(at) as usize == index;
+    if header[0] != 1 || !(local(4) && local(8) && local(14)) || field(12) != 32 {
+        return None;
+    }
+    let (pk_start, msg_start) = (field(6) as usize, field(10) as usize);
+    let signer = ix.data.get(pk_start..pk_start + 32)?.try_into().ok()?;
+    let message = ix.data.get(msg_start..msg_start + 32)?.try_into().ok()?;
+    Some((Pubkey::new_from_array(signer), message))
+}
+
+/// Runs `parse` over every instruction ahead of the current one and keeps
+/// what it extracts. Verify instructions may sit at any of these indices,
+/// e.g. behind compute-budget instructions, and each is parsed only once no
+/// matter how many receipts it's checked against.
+/// Requires a Secp256k1 attestation of `message` by `relayer_eth_address`.
+fn require_eth_attestation(
+    state: &State,
+    ix_sysvar: &AccountInfo,
+    message: &[u8; 32],
+) -> Result<()> {
+    // A single Ethereum key can't satisfy an M-of-N relayer policy.
+    require!(state.required_signatures() == 1, ErrorCode::ThresholdNotMet);
+    let expected = state.relayer_eth_address;
+    let signed = collect_attestations(ix_sysvar, secp256k1_attestation)?
+        .iter()
+        .any(|(eth_address, signed)| *eth_address == expected && signed == message);
+    require!(expected != [0u8; 20] && signed, ErrorCode::InvalidSignature);
+    Ok(())
+}
+
+fn collect_attestations<T>(
+    ix_sysvar: &AccountInfo,
+    parse: fn(&Instruction, usize) -> Option<T>,
+) -> Result<Vec<T>> {
+    let current = load_current_index_checked(ix_sysvar)? as usize;
+    let mut attestations = Vec::new();
+    for i in 0..current {
+        if let Some(attestation) = parse(&load_instruction_at_checked(i, ix_sysvar)?, i) {
+            attestations.push(attestation);
+        }
+    }
+    Ok(attestations)
+}
+
+/// Requires Ed25519 verify instructions ahead of this one from enough
+/// distinct enrolled relayers, one instruction per co-signer, and returns the
+/// signers in instruction order.
+fn require_relayer_quorum(
+    state: &State,
+    ix_sysvar: &AccountInfo,
+    message: &[u8; 32],
+) -> Result<Vec<Pubkey>> {
+    let attestations = collect_attestations(ix_sysvar, ed25519_attestation)?;
+    let signers = relayer_signers(state, &attestations, message);
+    require!(!signers.is_empty(), ErrorCode::InvalidSignature);
+    require!(
+        signers.len() >= state.required_signatures(),
+        ErrorCode::ThresholdNotMet
+    );
+    Ok(signers)
+}
+
+/// Collects the distinct enrolled relayers with an Ed25519 attestation over
+/// `message`.
+fn relayer_signers(
+    state: &State,
+    attestations: &[(Pubkey, [u8; 32])],
+    message: &[u8; 32],
+) -> Vec<Pubkey> {
+    let mut signers = Vec::new();
+    for (signer, signed) in attestations {
+        if signed == message && state.is_relayer(signer) && !signers.contains(signer) {
+            signers.push(*signer);
+        }
+    }
+    signers
+}
+
+/// Parses the Secp256k1 verify instruction at `index` into the Ethereum
+/// address it recovered and the 32-byte message it covers, under the same
+/// rules as `ed25519_attestation`.
+fn secp256k1_attestation(ix: &Instruction, index: usize) -> Option<([u8; 20], [u8; 32])> {
+    if ix.program_id != secp256k1_program::ID {
+        return None;
+    }
+
+    // Secp256k1 instruction data format:
+    // [0]: number of signatures (u8)
+    // [1..3]: signature offset (u16)
+    // [3]: signature instruction index (u8)
+    // [4..6]: eth address offset (u16)
+    // [6]: eth address instruction index (u8)
+    // [7..9]: message data offset (u16)
+    // [9..11]: message data size (u16)
+    // [11]: message instruction index (u8)
+    let header = ix.data.get(..12)?;
+    let field = |at: usize| u16::from_le_bytes([header[at], header[at + 1]]) as usize;
+    let local = |at: usize| header[at] as usize == index;
+    if header[0] != 1 || !(local(3) && local(6) && local(11)) || field(9) != 32 {
+        return None;
+    }
+    let (eth_start, msg_start) = (field(4), field(7));
+    let eth_address = ix.data.get(eth_start..eth_start + 20)?.try_into().ok()?;
+    let message = ix.data.get(msg_start..msg_start + 32)?.try_into().ok()?;
+    Some((eth_address, message))
+}
+
+/// Every status change advances the nonce so a receipt signature is only
+/// ever valid for the state it was issued against, and is announced through
+/// `StatusChanged` for indexers that track the whole lifecycle.
+fn transition(req: &mut Account<Request>, status: Status) -> Result<()> {
+    let old_status = std::mem::replace(&mut req.status, status);
+    req.nonce += 1;
+    req.updated_at = Clock::get()?.unix_timestamp;
+    emit!(StatusChanged {
+        request_id: req.key(),
+        old_status,
+        new_status: req.status.clone(),
+        timestamp: req.updated_at,
+    });
+    Ok(())
+}
+
+fn confirm_request(
+    state: &mut State,
+    user_state: &mut UserState,
+    req: &mut Account<Request>,
+    escrow: &AccountInfo,
+    vault: &mut Account<Vault>,
+    receipt: &Receipt,
+    confirmed_by: Pubkey,
+) -> Result<()> {
+    require!(!state.is_paused(PAUSE_CONFIRM), ErrorCode::Paused);
+    // Queued requests wait for initiation to reopen before being served.
+    require!(
+        req.status != Status::Queued || !state.is_paused(PAUSE_INITIATE),
+        ErrorCode::Paused
+    );
+    let now = Clock::get()?.unix_timestamp;
+    receipt.check(now)?;
+    require!(
+        req.confirm_by == 0 || now <= req.confirm_by,
+        ErrorCode::ConfirmWindowElapsed
+    );
+    req.blob_id = receipt.blob_id;
+    req.sui_tx_hash = receipt.sui_tx_hash;
+    req.proof_hash = receipt.proof_hash;
+    req.sui_checkpoint = receipt.sui_checkpoint;
+    req.sui_object_ids = receipt.sui_object_ids;
+    req.confirmed_by = confirmed_by;
+    // Token payments accrue no fees, so all of it counts as storage cost.
+    req.fee_taken = if req.is_native() {
+        bps_of(req.payment, state.fee_bps)
+    } else {
+        0
+    };
+    req.storage_cost = req.payment - req.fee_taken;
+    transition(req, Status::Confirmed)?;
+    state.record_status(&req.status)?;
+    user_state.close_pending(state)?;
+    // The payment is earned once confirmed, so release it from escrow to the
+    // vault. Token payments already sit in the vault token account.
+    if req.is_native() {
+        state.unlock_payment(req.payment)?;
+        state.accrue_fee(req.key(), req.fee_taken)?;
+        vault.record_in(req.payment)?;
+        transfer_lamports(escrow, &vault.to_account_info(), req.payment)?;
+    } else {
+        state.unlock_tokens(req.payment);
+    }
+    emit!(StorageConfirmed {
+        request_id: req.key(),
+        blob_id: receipt.blob_id,
+        sui_tx_hash: receipt.sui_tx_hash,
+        proof_hash: receipt.proof_hash,
+        vault_balance: vault.get_lamports(),
+        sui_checkpoint: receipt.sui_checkpoint,
+        confirmed_by,
+        fee_taken: req.fee_taken,
+        storage_cost: req.storage_cost,
+        network_tag: state.network_tag,
+        sui_object_ids: receipt.sui_object_ids,
+    });
+    Ok(())
+}
+
+/// Fails a pending native request and refunds its escrowed payment, less
+/// `failure_fee_bps`, which is kept in the vault as fees for work already done.
+fn fail_request(
+    state: &mut State,
+    user_state: &mut UserState,
+    req: &mut Account<Request>,
+    escrow: &AccountInfo,
+    vault: &mut Account<Vault>,
+    refund_to: &AccountInfo,
+) -> Result<()> {
+    require_keys_eq!(refund_to.key(), req.refund_address(), ErrorCode::InvalidRecipient);
+    // Refunds come out of the escrow; a short one is a solvency bug, so say so
+    // instead of surfacing it as an overflow from `transfer_lamports`.
+    require!(escrow.lamports() >= req.payment, ErrorCode::InsufficientVaultBalance);
+    transition(req, Status::Failed)?;
+    state.record_status(&req.status)?;
+    state.record_failure()?;
+    state.unlock_payment(req.payment)?;
+    user_state.close_pending(state)?;
+    let retained = bps_of(req.payment, state.failure_fee_bps);
+    let refunded = req.payment - retained;
+    state.accrue_fee(req.key(), retained)?;
+    vault.record_in(retained)?;
+    let vault_ai = vault.to_account_info();
+    transfer_lamports(escrow, refund_to, refunded)?;
+    transfer_lamports(escrow, &vault_ai, retained)?;
+    check_vault_invariant(state, &vault_ai)?;
+    emit!(RequestFailed {
+        request_id: req.key(),
+        refunded,
+        retained,
+    });
+    Ok(())
+}
+
+/// Asserts the vault still covers its rent and the fees it owes. Pending
+/// payments (`locked_lamports`) sit in their escrows, so they aren't part of
+/// the vault's balance.
+fn check_vault_invariant(state: &State, vault: &AccountInfo) -> Result<()> {
+    let floor = Rent::get()?
+        .minimum_balance(vault.data_len())
+        .checked_add(state.accrued_fees)
+        .ok_or(ErrorCode::ArithmeticOverflow)?;
+    require!(vault.lamports() >= floor, ErrorCode::VaultInvariantViolated);
+    Ok(())
+}
+
+/// Transitions `admin_set_status` may make: between the two pending states,
+/// or among the settled ones whose payment already sits in the vault.
+fn status_override_allowed(from: &Status, to: &Status) -> bool {
+    use Status::*;
+    matches!(
+        (from, to),
+        (Pending, Queued)
+            | (Queued, Pending)
+            | (Confirmed, Revoked)
+            | (Confirmed, Expired)
+            | (Revoked, Confirmed)
+            | (Revoked, Expired)
+            | (Expired, Confirmed)
+    )
+}
+
+/// The balance of `account` if it is a reaped address (System-owned, no
+/// data), or zero.
+fn reaped_lamports(account: &AccountInfo) -> u64 {
+    if *account.owner == system_program::ID && account.data_is_empty() {
+        account.lamports()
+    } else {
+        0
+    }
+}
+
+/// Whether `data` starts with the discriminator of one of the program's
+/// account types, i.e. might still be in use.
+fn is_live_account(data: &[u8]) -> bool {
+    [
+        State::DISCRIMINATOR,
+        Vault::DISCRIMINATOR,
+        Escrow::DISCRIMINATOR,
+        Request::DISCRIMINATOR,
+        UserState::DISCRIMINATOR,
+        HashRegistry::DISCRIMINATOR,
+        RelayerBond::DISCRIMINATOR,
+    ]
+    .iter()
+    .any(|d| data.starts_with(d))
+}
+
+/// Applies new payment bounds for `configure` and the single setters alike,
+/// so both keep `min_payment <= max_payment` (zero max means unbounded) and
+/// emit the same events.
+fn set_payment_bounds(state: &mut State, min: Option<u64>, max: Option<u64>) -> Result<()> {
+    let old_min = state.min_payment;
+    let old_max = state.max_payment;
+    let new_min = min.unwrap_or(old_min);
+    let new_max = max.unwrap_or(old_max);
+    require!(
+        new_max == 0 || new_min <= new_max,
+        ErrorCode::InvalidPaymentBounds
+    );
+    state.min_payment = new_min;
+    state.max_payment = new_max;
+    if min.is_some() {
+        emit!(MinPaymentUpdated { old_min, new_min });
+    }
+    if max.is_some() {
+        emit!(MaxPaymentUpdated { old_max, new_max });
+    }
+    Ok(())
+}
+
+/// Accepts a fresh receipt for a challenged request before its deadline.
+fn resolve_challenge(
+    state: &State,
+    req: &mut Account<Request>,
+    receipt: &Receipt,
+    relayer: Pubkey,
+) -> Result<()> {
+    require!(!state.is_paused(PAUSE_CONFIRM), ErrorCode::Paused);
+    require!(
+        req.status == Status::Confirmed && req.challenged,
+        ErrorCode::InvalidStatus
+    );
+    let now = Clock::get()?.unix_timestamp;
+    require!(now <= req.challenge_deadline, ErrorCode::ChallengeExpired);
+    receipt.check(now)?;
+    req.blob_id = receipt.blob_id;
+    req.sui_tx_hash = receipt.sui_tx_hash;
+    req.proof_hash = receipt.proof_hash;
+    req.sui_checkpoint = receipt.sui_checkpoint;
+    req.sui_object_ids = receipt.sui_object_ids;
+    req.confirmed_by = relayer;
+    req.challenged = false;
+    req.challenge_deadline = 0;
+    req.nonce += 1;
+    emit!(ChallengeAnswered {
+        request_id: req.key(),
+        blob_id: receipt.blob_id,
+        relayer,
+    });
+    Ok(())
+}
+
+fn add_delegate(req: &mut Account<Request>, delegate: Pubkey, expires_at: i64) -> Result<()> {
+    require!(delegate != Pubkey::default(), ErrorCode::InvalidDelegate);
+    let grant = DelegateGrant {
+        key: delegate,
+        expires_at,
+    };
+    match req.delegate_index(&delegate) {
+        // A lapsed grant is renewed in its slot.
+        Some(i) => {
+            let now = Clock::get()?.unix_timestamp;
+            require!(!req.delegates[i].is_active(now), ErrorCode::DuplicateDelegate);
+            req.delegates[i] = grant;
+        }
+        None => {
+            let count = req.delegate_count as usize;
+            require!(count < MAX_DELEGATES, ErrorCode::DelegateListFull);
+            req.delegates[count] = grant;
+            req.delegate_count += 1;
+        }
+    }
+    emit!(AccessGranted {
+        request_id: req.key(),
+        delegate,
+        expires_at,
+    });
+    Ok(())
+}
+
+/// Tops `bond` up to `required` lamports from the relayer's own account.
+fn post_bond<'info>(
+    bond: &mut Account<'info, RelayerBond>,
+    relayer: Pubkey,
+    required: u64,
+    system_program: &Program<'info, System>,
+    relayer_signer: &Signer<'info>,
+) -> Result<()> {
+    let top_up = required.saturating_sub(bond.amount);
+    bond.relayer = relayer;
+    bond.amount = bond
+        .amount
+        .checked_add(top_up)
+        .ok_or(ErrorCode::ArithmeticOverflow)?;
+    deposit(system_program, relayer_signer, &bond.to_account_info(), top_up)
+}
+
+/// Loads the owner's `UserState` for a request passed through
+/// `remaining_accounts`.
+fn batch_user_state<'info>(
+    user_state: &'info AccountInfo<'info>,
+    req: &Request,
+) -> Result<Account<'info, UserState>> {
+    let (user_state_key, _) =
+        Pubkey::find_program_address(&[b"user", req.user.as_ref()], &crate::ID);
+    require_keys_eq!(user_state.key(), user_state_key, ErrorCode::InvalidBatch);
+    Account::try_from(user_state)
+}
+
+/// Charges the flat, non-refundable `creation_fee` straight into the vault's
+/// accrued fees.
+fn collect_creation_fee<'info>(
+    state: &mut State,
+    request_id: Pubkey,
+    system_program: &Program<'info, System>,
+    payer: &Signer<'info>,
+    vault: &mut Account<'info, Vault>,
+) -> Result<()> {
+    let fee = state.creation_fee;
+    if fee == 0 {
+        return Ok(());
+    }
+    state.accrue_fee(request_id, fee)?;
+    vault.record_in(fee)?;
+    deposit(system_program, payer, &vault.to_account_info(), fee)
+}
+
+/// Creates the PDA `account`, owned by the program and funded by `payer`, and
+/// stamps `discriminator` so it loads as a zeroed account of that type.
+fn create_program_account<'info>(
+    system_program: &Program<'info, System>,
+    payer: &Signer<'info>,
+    account: &AccountInfo<'info>,
+    space: usize,
+    discriminator: &[u8],
+    seeds: &[&[u8]],
+) -> Result<()> {
+    let rent = Rent::get()?.minimum_balance(space);
+    let program = system_program.to_account_info();
+    if account.lamports() == 0 {
+        system_program::create_account(
+            CpiContext::new_with_signer(
+                program,
+                system_program::CreateAccount {
+                    from: payer.to_account_info(),
+                    to: account.clone(),
+                },
+                &[seeds],
+            ),
+            rent,
+            space as u64,
+            &crate::ID,
+        )?;
+    } else {
+        // `create_account` refuses an address that already holds lamports, so
+        // one funded ahead of time is topped up, allocated and assigned the
+        // way Anchor's `init` does.
+        let top_up = rent.saturating_sub(account.lamports());
+        if top_up > 0 {
+            deposit(system_program, payer, account, top_up)?;
+        }
+        system_program::allocate(
+            CpiContext::new_with_signer(
+                program.clone(),
+                system_program::Allocate {
+                    account_to_allocate: account.clone(),
+                },
+                &[seeds],
+            ),
+            space as u64,
+        )?;
+        system_program::assign(
+            CpiContext::new_with_signer(
+                program,
+                system_program::Assign {
+                    account_to_assign: account.clone(),
+                },
+                &[seeds],
+            ),
+            &crate::ID,
+        )?;
+    }
+    account.try_borrow_mut_data()?[..8].copy_from_slice(discriminator);
+    Ok(())
+}
+
+/// Reallocates a program-owned account up to `space` bytes, topping up its
+/// rent from `payer`. Accounts already that large are left alone.
+fn grow_account<'info>(
+    account: &AccountInfo<'info>,
+    space: usize,
+    payer: &Signer<'info>,
+    system_program: &Program<'info, System>,
+) -> Result<()> {
+    if account.data_len() >= space {
+        return Ok(());
+    }
+    let top_up = Rent::get()?
+        .minimum_balance(space)
+        .saturating_sub(account.lamports());
+    if top_up > 0 {
+        deposit(system_program, payer, account, top_up)?;
+    }
+    account.realloc(space, true)?;
+    Ok(())
+}
+
+/// Caller-supplied terms shared by every storage-initiation instruction.
+struct NewRequest {
+    data_hash: [u8; 32],
+    payment: u64,
+    data_size: u64,
+    duration_secs: i64,
+    content_type: [u8; 16],
+    enc_key_commitment: [u8; 32],
+    priority: u8,
+    /// When set, the quoted price is charged instead of `payment`, as long as
+    /// it doesn't exceed this cap.
+    max_acceptable_payment: Option<u64>,
+    memo: [u8; 32],
+    approved_relayer: Pubkey,
+    tier: u8,
+    refund_to: Pubkey,
+    tags: [u8; 4],
+}
+
+/// The shared start of the single-request initiate instructions: the dedup
+/// and rate-limit checks, then `open_request`.
+#[allow(clippy::too_many_arguments)]
+fn open_single_request(
+    state: &mut State,
+    user_state: &mut UserState,
+    req: &mut Account<Request>,
+    hash_registry: &AccountInfo,
+    user: Pubkey,
+    dedup: bool,
+    params: NewRequest,
+) -> Result<()> {
+    // A registry entry only exists while a confirmed request holds the hash.
+    require!(
+        !dedup || hash_registry.data_is_empty(),
+        ErrorCode::DuplicateDataHash
+    );
+    user_state.throttle(state)?;
+    open_request(state, user_state, req, user, params)
+}
+
+/// Escrows a freshly opened native request's payment from `payer` and
+/// charges the creation fee, after every state write, then announces the
+/// request.
+fn fund_native_request<'info>(
+    state: &mut State,
+    req: &Account<'info, Request>,
+    system_program: &Program<'info, System>,
+    payer: &Signer<'info>,
+    escrow: &AccountInfo<'info>,
+    vault: &mut Account<'info, Vault>,
+) -> Result<()> {
+    state.lock_payment(req.payment)?;
+    collect_creation_fee(state, req.key(), system_program, payer, vault)?;
+    deposit(system_program, payer, escrow, req.payment)?;
+    emit_storage_requested(state, req, vault.get_lamports());
+    check_vault_invariant(state, &vault.to_account_info())
+}
+
+fn open_request(
+    state: &mut State,
+    user_state: &mut UserState,
+    req: &mut Account<Request>,
+    user: Pubkey,
+    params: NewRequest,
+) -> Result<()> {
+    let NewRequest {
+        data_hash,
+        payment,
+        data_size,
+        duration_secs,
+        content_type,
+        enc_key_commitment,
+        priority,
+        max_acceptable_payment,
+        memo,
+        approved_relayer,
+        tier,
+        refund_to,
+        tags,
+    } = params;
+    // With `queue_when_paused`, requests opened while paused are still
+    // escrowed, as `Queued`, for relayers to pick up once unpaused.
+    let queued = state.is_paused(PAUSE_INITIATE);
+    require!(!queued || state.queue_when_paused, ErrorCode::Paused);
+    // All-zero hashes are the "unset" sentinel for blob_id and friends.
+    require!(data_hash != [0u8; 32], ErrorCode::InvalidDataHash);
+    let tier_config = state.tier_config(tier)?;
+    // A zero duration falls back to the tier's default term.
+    let duration_secs = match duration_secs {
+        0 => tier_config.default_duration_secs,
+        d => d,
+    };
+    require!(duration_secs > 0, ErrorCode::InvalidDuration);
+    require!(content_type != [0u8; 16], ErrorCode::InvalidContentType);
+    require!(
+        state.accepts_content_type(&content_type),
+        ErrorCode::UnsupportedContentType
+    );
+    require!(priority <= MAX_PRIORITY, ErrorCode::InvalidPriority);
+    require!(!state.exceeds_max_data_size(data_size), ErrorCode::DataTooLarge);
+    let required = state.tier_payment(tier, data_size)?;
+    let payment = match max_acceptable_payment {
+        Some(max) => {
+            require!(required <= max, ErrorCode::PaymentAboveMax);
+            required
+        }
+        None => {
+            require!(payment >= required, ErrorCode::InsufficientPayment);
+            payment
+        }
+    };
+    require!(!state.exceeds_max_payment(payment), ErrorCode::PaymentTooLarge);
+    req.user = user;
+    req.data_hash = data_hash;
+    req.blob_id = [0u8; 32];
+    req.sui_tx_hash = [0u8; 32];
+    req.proof_hash = [0u8; 32];
+    req.sui_checkpoint = 0;
+    req.sui_object_ids = [[0u8; 32]; 2];
+    req.confirmed_by = Pubkey::default();
+    req.fee_taken = 0;
+    req.storage_cost = 0;
+    req.status = if queued {
+        Status::Queued
+    } else {
+        Status::Pending
+    };
+    req.nonce = 0;
+    req.payment = payment;
+    req.data_size = data_size;
+    req.content_type = content_type;
+    req.enc_key_commitment = enc_key_commitment;
+    req.priority = priority;
+    req.memo = memo;
+    req.approved_relayer = approved_relayer;
+    req.tier = tier;
+    req.refund_to = refund_to;
+    req.tags = tags;
+    req.timestamp = Clock::get()?.unix_timestamp;
+    req.updated_at = req.timestamp;
+    req.duration_secs = duration_secs;
+    req.expiry = req
+        .timestamp
+        .checked_add(duration_secs)
+        .ok_or(ErrorCode::ArithmeticOverflow)?;
+    req.payment_mint = Pubkey::default();
+    req.confirm_by = state.confirm_deadline(req.timestamp)?;
+    state.count = state
+        .count
+        .checked_add(1)
+        .ok_or(ErrorCode::ArithmeticOverflow)?;
+    user_state.request_count = user_state
+        .request_count
+        .checked_add(1)
+        .ok_or(ErrorCode::ArithmeticOverflow)?;
+    user_state.open_pending(state)?;
+    Ok(())
+}
+
+/// Announces an opened request once it's funded, so `vault_balance` includes
+/// its creation fee.
+fn emit_storage_requested(state: &State, req: &Account<Request>, vault_balance: u64) {
+    emit!(StorageRequested {
+        request_id: req.key(),
+        user: req.user,
+        data_hash: req.data_hash,
+        payment: req.payment,
+        timestamp: req.timestamp,
+        data_size: req.data_size,
+        content_type: req.content_type,
+        enc_key_commitment: req.enc_key_commitment,
+        vault_balance,
+        priority: req.priority,
+        memo: req.memo,
+        tier: req.tier,
+        network_tag: state.network_tag,
+        tags: req.tags,
+    });
+}
+
+/// Pays tokens out of a vault-owned token account, signing as the vault PDA.
+fn refund_tokens<'info>(
+    token_program: &Program<'info, Token>,
+    vault_token: &Account<'info, TokenAccount>,
+    user_token: &Account<'info, TokenAccount>,
+    vault: &Account<'info, Vault>,
+    vault_bump: u8,
+    amount: u64,
+) -> Result<()> {
+    token::transfer(
+        CpiContext::new_with_signer(
+            token_program.to_account_info(),
+            token::Transfer {
+                from: vault_token.to_account_info(),
+                to: user_token.to_account_info(),
+                authority: vault.to_account_info(),
+            },
+            &[&[b"vault", &[vault_bump]]],
+        ),
+        amount,
+    )
+}
+
+/// `amount * bps / 10_000`, with `bps` capped at `MAX_BPS` by the setters.
+fn bps_of(amount: u64, bps: u16) -> u64 {
+    (amount as u128 * bps as u128 / MAX_BPS as u128) as u64
+}
+
+/// Pays `amount` lamports from a signer into one of the program's accounts.
+///
+/// Lamport-moving paths follow checks-effects-interactions: every state write
+/// lands before `deposit`, `transfer_lamports` or a token CPI runs, creation
+/// fees included (`initiate_storage_batch` does this item by item). The only
+/// CPIs are to the System and Token programs, which never call back in, so
+/// there is no reentrancy guard on `State`.
+fn deposit<'info>(
+    system_program: &Program<'info, System>,
+    from: &Signer<'info>,
+    to: &AccountInfo<'info>,
+    amount: u64,
+) -> Result<()> {
+    require!(from.lamports() >= amount, ErrorCode::InsufficientFunds);
+    system_program::transfer(
+        CpiContext::new(
+            system_program.to_account_info(),
+            system_program::Transfer {
+                from: from.to_account_info(),
+                to: to.clone(),
+            },
+        ),
+        amount,
+    )
+}
+
+/// Moves lamports out of an account owned by this program.
+fn transfer_lamports(from: &AccountInfo, to: &AccountInfo, amount: u64) -> Result<()> {
+    let from_balance = from
+        .lamports()
+        .checked_sub(amount)
+        .ok_or(ErrorCode::ArithmeticOverflow)?;
+    let to_balance = to
+        .lamports()
+        .checked_add(amount)
+        .ok_or(ErrorCode::ArithmeticOverflow)?;
+    **from.lamports.borrow_mut() = from_balance;
+    **to.lamports.borrow_mut() = to_balance;
+    Ok(())
+}
+
+// New fields are only ever appended. A `State` account created by an older
+// build is shorter than `8 + State::INIT_SPACE` and has to be reallocated to
+// the current size with `migrate_state` before these trailing fields can be
+// read.
+#[account]
+#[derive(InitSpace)]
+pub struct State {
+    pub admin: Pubkey,
+    pub relayer: Pubkey,
+    pub pause_flags: u8,
+    pub count: u64,
+    pub pending_admin: Pubkey,
+    pub relayers: [Pubkey; MAX_RELAYERS],
+    pub relayer_count: u8,
+    pub min_payment: u64,
+    pub base_fee: u64,
+    pub per_byte_fee: u64,
+    pub timeout_secs: i64,
+    pub payment_mint: Pubkey,
+    pub fee_bps: u16,
+    pub accrued_fees: u64,
+    pub confirmed_count: u64,
+    pub failed_count: u64,
+    pub revoked_count: u64,
+    pub relayer_eth_address: [u8; 20],
+    pub threshold: u8,
+    pub guardian: Pubkey,
+    pub withdraw_delay_secs: i64,
+    pub pending_withdraw_amount: u64,
+    pub withdraw_unlock_time: i64,
+    pub locked_lamports: u64,
+    pub max_pending_per_user: u16,
+    pub confirm_window_secs: i64,
+    pub failure_fee_bps: u16,
+    /// Canonical bumps, stored so constraints don't re-derive them.
+    pub state_bump: u8,
+    pub vault_bump: u8,
+    pub fee_recipient: Pubkey,
+    pub max_payment: u64,
+    pub creation_fee: u64,
+    /// Service levels, indexed by `Request::tier`. The relayer maps each to a
+    /// replication policy.
+    pub tiers: [TierConfig; MAX_TIERS],
+    /// Layout version. Accounts from before versioning read as zero.
+    pub version: u8,
+    pub network_tag: u8,
+    pub breaker_threshold: u16,
+    pub breaker_window_secs: i64,
+    /// Failures counted since `breaker_window_start`.
+    pub breaker_failures: u16,
+    pub breaker_window_start: i64,
+    /// Requests currently pending, across all users and payment kinds.
+    pub pending_count: u64,
+    /// Lamports each relayer posts when enrolled through `add_relayer`.
+    pub relayer_bond: u64,
+    /// Accept new requests as `Queued` instead of rejecting them while
+    /// `PAUSE_INITIATE` is set.
+    pub queue_when_paused: bool,
+    /// Largest blob, in bytes, a request may declare, or zero for no limit.
+    pub max_data_size: u64,
+    pub challenge_window_secs: i64,
+    /// Minimum seconds between one user's submissions, or zero for no limit.
+    pub min_request_interval: i64,
+    /// Content types requests may declare; all-zero slots are unused.
+    pub allowed_content_types: [[u8; 16]; MAX_CONTENT_TYPES],
+    /// Token payments of pending requests, across all payment mints.
+    pub locked_tokens: u64,
+    pub pending_token_withdraw_amount: u64,
+    pub token_withdraw_unlock_time: i64,
+    /// A lower `withdraw_delay_secs`, applicable once its unlock time (zero
+    /// while nothing is queued) passes.
+    pub pending_withdraw_delay_secs: i64,
+    pub withdraw_delay_unlock_time: i64,
+    pub pending_fee_recipient: Pubkey,
+    pub fee_recipient_unlock_time: i64,
+    /// `payout_address()` when `PAUSE_INITIATE` was last set, the only
+    /// account `emergency_withdraw` pays.
+    pub emergency_recipient: Pubkey,
+}
+impl State {
+    pub fn active_relayers(&self) -> &[Pubkey] {
+        &self.relayers[..self.relayer_count as usize]
+    }
+    pub fn relayer_index(&self, key: &Pubkey) -> Option<usize> {
+        self.active_relayers().iter().position(|r| r == key)
+    }
+    pub fn is_relayer(&self, key: &Pubkey) -> bool {
+        self.relayer_index(key).is_some()
+    }
+    /// The payment owed for a blob of `data_size` bytes: the size-based
+    /// price, floored at `min_payment`.
+    pub fn required_payment(&self, data_size: u64) -> Result<u64> {
+        let price = (self.per_byte_fee as u128)
+            .checked_mul(data_size as u128)
+            .and_then(|p| p.checked_add(self.base_fee as u128))
+            .and_then(|p| u64::try_from(p).ok())
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        Ok(price.max(self.min_payment))
+    }
+    /// `required_payment`, floored at the tier's own minimum.
+    pub fn tier_payment(&self, tier: u8, data_size: u64) -> Result<u64> {
+        let min_payment = self.tier_config(tier)?.min_payment;
+        Ok(self.required_payment(data_size)?.max(min_payment))
+    }
+    pub fn tier_config(&self, tier: u8) -> Result<TierConfig> {
+        self.tiers
+            .get(tier as usize)
+            .copied()
+            .ok_or(error!(ErrorCode::InvalidTier))
+    }
+    /// The payment owed to extend storage by `additional_secs`, charged pro
+    /// rata against the price of the original `term_secs` term.
+    pub fn renewal_payment(
+        &self,
+        data_size: u64,
+        term_secs: i64,
+        additional_secs: i64,
+    ) -> Result<u64> {
+        require!(term_secs > 0 && additional_secs > 0, ErrorCode::InvalidDuration);
+        let term = term_secs as u128;
+        (self.required_payment(data_size)? as u128)
+            .checked_mul(additional_secs as u128)
+            .map(|p| p.div_ceil(term))
+            .and_then(|p| u64::try_from(p).ok())
+            .ok_or(error!(ErrorCode::ArithmeticOverflow))
+    }
+    /// When a request opened at `opened_at` stops being confirmable. A zero
+    /// window disables the limit.
+    pub fn confirm_deadline(&self, opened_at: i64) -> Result<i64> {
+        if self.confirm_window_secs == 0 {
+            return Ok(0);
+        }
+        opened_at
+            .checked_add(self.confirm_window_secs)
+            .ok_or(error!(ErrorCode::ArithmeticOverflow))
+    }
+    /// A zero `max_payment` leaves payments uncapped.
+    pub fn exceeds_max_payment(&self, payment: u64) -> bool {
+        self.max_payment != 0 && payment > self.max_payment
+    }
+    pub fn exceeds_max_data_size(&self, data_size: u64) -> bool {
+        self.max_data_size != 0 && data_size > self.max_data_size
+    }
+    /// An allowlist of all-zero entries accepts any content type.
+    pub fn accepts_content_type(&self, content_type: &[u8; 16]) -> bool {
+        let allowed = &self.allowed_content_types;
+        allowed.iter().all(|t| *t == [0u8; 16]) || allowed.contains(content_type)
+    }
+    /// Gives fields an older layout predates, read back as zero, the values
+    /// `initialize` would have set.
+    fn fill_migration_defaults(&mut self) {
+        // The baseline only had the single `relayer`.
+        if self.relayer_count == 0 && self.relayer != Pubkey::default() {
+            self.relayers[0] = self.relayer;
+            self.relayer_count = 1;
+        }
+        if self.threshold == 0 {
+            self.threshold = 1;
+        }
+        if self.timeout_secs == 0 {
+            self.timeout_secs = DEFAULT_TIMEOUT_SECS;
+        }
+        if self.min_payment == 0 {
+            self.min_payment = DEFAULT_MIN_PAYMENT;
+        }
+        if self.max_payment == 0 {
+            self.max_payment = DEFAULT_MAX_PAYMENT;
+        }
+    }
+    /// Where withdrawals are paid: `fee_recipient`, or the admin while unset.
+    pub fn payout_address(&self) -> Pubkey {
+        if self.fee_recipient == Pubkey::default() {
+            self.admin
+        } else {
+            self.fee_recipient
+        }
+    }
+    fn write_fee_recipient(&mut self, new_recipient: Pubkey) {
+        let old_recipient = self.fee_recipient;
+        self.fee_recipient = new_recipient;
+        emit!(FeeRecipientUpdated {
+            old_recipient,
+            new_recipient,
+        });
+    }
+    pub fn is_paused(&self, flag: u8) -> bool {
+        self.pause_flags & flag != 0
+    }
+    /// How many distinct relayers must co-sign a receipt. States created
+    /// before the threshold existed read it as zero, meaning one.
+    pub fn required_signatures(&self) -> usize {
+        self.threshold.max(1) as usize
+    }
+    fn accrue_fee(&mut self, request_id: Pubkey, fee: u64) -> Result<()> {
+        if fee == 0 {
+            return Ok(());
+        }
+        self.accrued_fees = self
+            .accrued_fees
+            .checked_add(fee)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        emit!(FeesAccrued {
+            request_id,
+            fee,
+            accrued_fees: self.accrued_fees,
+        });
+        Ok(())
+    }
+    /// Sets `pause_flags` and announces it; nothing else writes them after `initialize`.
+    fn write_pause_flags(&mut self, flags: u8, authority: Pubkey) -> Result<()> {
+        let old_flags = std::mem::replace(&mut self.pause_flags, flags);
+        if old_flags & PAUSE_INITIATE == 0 && flags & PAUSE_INITIATE != 0 {
+            self.emergency_recipient = self.payout_address();
+        }
+        emit!(PauseFlagsUpdated {
+            authority,
+            old_flags,
+            new_flags: flags,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+    /// Counts a failed request against the circuit breaker, pausing
+    /// everything once `breaker_threshold` land within one window.
+    fn record_failure(&mut self) -> Result<()> {
+        if self.breaker_threshold == 0 {
+            return Ok(());
+        }
+        let now = Clock::get()?.unix_timestamp;
+        if now.saturating_sub(self.breaker_window_start) >= self.breaker_window_secs {
+            self.breaker_window_start = now;
+            self.breaker_failures = 0;
+        }
+        self.breaker_failures = self.breaker_failures.saturating_add(1);
+        if self.breaker_failures >= self.breaker_threshold {
+            // The breaker has no signer behind it.
+            self.write_pause_flags(PAUSE_ALL, Pubkey::default())?;
+            emit!(CircuitBreakerTripped {
+                failures: self.breaker_failures,
+                window_secs: self.breaker_window_secs,
+                timestamp: now,
+            });
+            self.breaker_failures = 0;
+        }
+        Ok(())
+    }
+    fn record_status(&mut self, status: &Status) -> Result<()> {
+        let counter = match status {
+            Status::Confirmed => &mut self.confirmed_count,
+            Status::Failed => &mut self.failed_count,
+            Status::Revoked => &mut self.revoked_count,
+            _ => return Ok(()),
+        };
+        *counter = counter
+            .checked_add(1)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        Ok(())
+    }
+    /// `locked_lamports` tracks native payments held in escrow for pending
+    /// requests, so it can be read without scanning request accounts.
+    fn lock_payment(&mut self, amount: u64) -> Result<()> {
+        self.locked_lamports = self
+            .locked_lamports
+            .checked_add(amount)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        Ok(())
+    }
+    fn unlock_payment(&mut self, amount: u64) -> Result<()> {
+        self.locked_lamports = self
+            .locked_lamports
+            .checked_sub(amount)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        Ok(())
+    }
+    /// `locked_tokens` is the token-payment counterpart of `locked_lamports`,
+    /// for payments that sit in a vault token account until confirmed.
+    fn lock_tokens(&mut self, amount: u64) -> Result<()> {
+        self.locked_tokens = self
+            .locked_tokens
+            .checked_add(amount)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        Ok(())
+    }
+    fn unlock_tokens(&mut self, amount: u64) {
+        // Token requests opened before `locked_tokens` existed were never
+        // counted in it.
+        self.locked_tokens = self.locked_tokens.saturating_sub(amount);
+    }
+    fn push_relayer(&mut self, relayer: Pubkey) -> Result<()> {
+        require!(
+            (self.relayer_count as usize) < MAX_RELAYERS,
+            ErrorCode::RelayerListFull
+        );
+        self.relayers[self.relayer_count as usize] = relayer;
+        self.relayer_count += 1;
+        Ok(())
+    }
+}
+/// Per-user bookkeeping. Request PDAs derive from the owner's own
+/// `request_count`, so users don't contend on the global count and can
+/// predict their next request address.
+#[account]
+#[derive(InitSpace)]
+pub struct UserState {
+    pub request_count: u64,
+    pub pending_count: u16,
+    pub last_request_time: i64,
+}
+impl UserState {
+    /// Enforces `State::min_request_interval` between the owner's
+    /// submissions, where zero means unthrottled.
+    fn throttle(&mut self, state: &State) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        require!(
+            now.saturating_sub(self.last_request_time) >= state.min_request_interval,
+            ErrorCode::RateLimited
+        );
+        self.last_request_time = now;
+        Ok(())
+    }
+    /// Counts a newly pending request against the owner's
+    /// `max_pending_per_user`, where zero means unlimited, and in the global
+    /// `State::pending_count`.
+    fn open_pending(&mut self, state: &mut State) -> Result<()> {
+        let limit = state.max_pending_per_user;
+        require!(
+            limit == 0 || self.pending_count < limit,
+            ErrorCode::TooManyPendingRequests
+        );
+        self.pending_count = self
+            .pending_count
+            .checked_add(1)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        state.pending_count = state
+            .pending_count
+            .checked_add(1)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        Ok(())
+    }
+    fn close_pending(&mut self, state: &mut State) -> Result<()> {
+        self.pending_count = self
+            .pending_count
+            .checked_sub(1)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        // Requests opened before `State::pending_count` existed were never
+        // counted in it.
+        state.pending_count = state.pending_count.saturating_sub(1);
+        Ok(())
+    }
+}
+/// A relayer's slashable bond, held as lamports above the account's rent.
+#[account]
+#[derive(InitSpace)]
+pub struct RelayerBond {
+    pub relayer: Pubkey,
+    pub amount: u64,
+}
+#[account]
+#[derive(InitSpace)]
+pub struct HashRegistry {
+    pub request: Pubkey,
+    /// Paid the entry's rent, and gets it back from `release_hash`.
+    pub payer: Pubkey,
+}
+/// Holds earned lamports. Pending payments sit in per-request escrows and are
+/// counted in `State::locked_lamports` instead.
+///
+/// Instructions always take the vault seeded by `[b"vault"]`, never as a plain
+/// `AccountInfo`, so a look-alike vault is rejected before any lamports move.
+#[account]
+#[derive(InitSpace)]
+pub struct Vault {
+    /// Lamports paid in by the program since the vault was created or
+    /// migrated. Rent and direct transfers aren't counted.
+    pub total_in: u64,
+    pub total_out: u64,
+}
+impl Vault {
+    fn record_in(&mut self, amount: u64) -> Result<()> {
+        self.total_in = self
+            .total_in
+            .checked_add(amount)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        Ok(())
+    }
+    fn record_out(&mut self, amount: u64) -> Result<()> {
+        self.total_out = self
+            .total_out
+            .checked_add(amount)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        Ok(())
+    }
+}
+#[account]
+pub struct Escrow {}
+#[account]
+#[derive(InitSpace)]
+pub struct Request {
+    pub user: Pubkey,
+    pub data_hash: [u8; 32],
+    pub blob_id: [u8; 32],
+    pub sui_tx_hash: [u8; 32],
+    pub proof_hash: [u8; 32],
+    pub status: Status,
+    pub payment: u64,
+    pub timestamp: i64,
+    pub nonce: u64,
+    pub data_size: u64,
+    pub expiry: i64,
+    pub payment_mint: Pubkey,
+    pub content_type: [u8; 16],
+    pub delegates: [DelegateGrant; MAX_DELEGATES],
+    pub delegate_count: u8,
+    pub duration_secs: i64,
+    /// Opaque commitment to the client-side encryption key or scheme, for
+    /// off-chain key management. All zeroes means the blob is unencrypted.
+    pub enc_key_commitment: [u8; 32],
+    /// Last unix timestamp at which the request can be confirmed, or zero for
+    /// no limit.
+    pub confirm_by: i64,
+    /// Relayer ordering hint, from 0 up to `MAX_PRIORITY`.
+    pub priority: u8,
+    pub sui_checkpoint: u64,
+    /// The relayer credited with the confirmation: the approved relayer if
+    /// set, else the first co-signer.
+    pub confirmed_by: Pubkey,
+    /// Free-form label for explorers, opaque (nominally UTF-8) to the program.
+    pub memo: [u8; 32],
+    /// The only relayer allowed to confirm, or the default key for any.
+    pub approved_relayer: Pubkey,
+    /// Index into `State::tiers`.
+    pub tier: u8,
+    /// Where native refunds go, or the default key for the owner.
+    pub refund_to: Pubkey,
+    /// How the payment was split at confirmation: the protocol fee kept from
+    /// it, and the rest, which pays for storage.
+    pub fee_taken: u64,
+    pub storage_cost: u64,
+    /// When `status` last changed. `timestamp` stays the creation time.
+    pub updated_at: i64,
+    /// Set by `challenge_request` until a fresh receipt answers it.
+    pub challenged: bool,
+    pub challenge_deadline: i64,
+    /// Owner-chosen category labels for indexers; the program never reads them.
+    pub tags: [u8; 4],
+    /// Sui objects from the latest receipt, see `Receipt::sui_object_ids`.
+    pub sui_object_ids: [[u8; 32]; 2],
+}
+impl Request {
+    pub fn active_delegates(&self) -> &[DelegateGrant] {
+        &self.delegates[..self.delegate_count as usize]
+    }
+    pub fn delegate_index(&self, key: &Pubkey) -> Option<usize> {
+        self.active_delegates().iter().position(|d| d.key == *key)
+    }
+    /// Whether `key` may read the blob at `now`: the owner or an enrolled,
+    /// unexpired delegate of a confirmed request.
+    pub fn can_access(&self, key: &Pubkey, now: i64) -> bool {
+        let delegate_active = self
+            .active_delegates()
+            .iter()
+            .any(|d| d.key == *key && d.is_active(now));
+        self.status == Status::Confirmed && (self.user == *key || delegate_active)
+    }
+    /// Picks the relayer to credit from a receipt's signers: the one the owner
+    /// approved, or the first signer when they didn't pick one.
+    pub fn approved_signer(&self, signers: &[Pubkey]) -> Result<Pubkey> {
+        signers
+            .iter()
+            .find(|s| self.approved_relayer == Pubkey::default() || self.approved_relayer == **s)
+            .copied()
+            .ok_or(error!(ErrorCode::RelayerNotApproved))
+    }
+    pub fn refund_address(&self) -> Pubkey {
+        if self.refund_to == Pubkey::default() {
+            self.user
+        } else {
+            self.refund_to
+        }
+    }
+    /// Awaiting confirmation, whether opened normally or queued during a pause.
+    pub fn is_pending(&self) -> bool {
+        matches!(self.status, Status::Pending | Status::Queued)
+    }
+    /// Native SOL payments leave `payment_mint` unset.
+    pub fn is_native(&self) -> bool {
+        self.payment_mint == Pubkey::default()
+    }
+}
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct Receipt {
+    pub blob_id: [u8; 32],
+    pub sui_tx_hash: [u8; 32],
+    /// `keccak(blob_id || sui_tx_hash)`, which catches relayers that swap or
+    /// mangle the other two fields.
+    pub proof_hash: [u8; 32],
+    /// Sui checkpoint that includes `sui_tx_hash`, for light-client proofs.
+    pub sui_checkpoint: u64,
+    /// Last unix timestamp at which the relayer's signature is accepted.
+    pub deadline: i64,
+    /// Sui objects the write produced (blob, metadata); all-zero if unused.
+    pub sui_object_ids: [[u8; 32]; 2],
+}
+impl Receipt {
+    fn check(&self, now: i64) -> Result<()> {
+        require!(now <= self.deadline, ErrorCode::SignatureExpired);
+        require!(
+            self.proof_hash == keccak::hashv(&[&self.blob_id, &self.sui_tx_hash]).0,
+            ErrorCode::InvalidProof
+        );
+        Ok(())
+    }
+}
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct VaultSummary {
+    pub total_lamports: u64,
+    pub accrued_fees: u64,
+    /// Native payments still escrowed for pending requests. These sit in the
+    /// per-request escrows, not in the vault.
+    pub locked_in_pending: u64,
+    /// Vault balance above rent and accrued fees, i.e. what `withdraw` allows.
+    pub withdrawable: u64,
+}
+/// Tunables for `configure`. Each `None` leaves its field unchanged.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct ConfigParams {
+    pub min_payment: Option<u64>,
+    pub max_payment: Option<u64>,
+    pub base_fee: Option<u64>,
+    pub per_byte_fee: Option<u64>,
+    pub fee_bps: Option<u16>,
+    pub failure_fee_bps: Option<u16>,
+    pub timeout_secs: Option<i64>,
+    pub confirm_window_secs: Option<i64>,
+    pub max_pending_per_user: Option<u16>,
+    pub max_data_size: Option<u64>,
+}
+/// Per-blob terms for `initiate_storage_batch`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct StorageItem {
+    pub data_hash: [u8; 32],
+    pub payment_amount: u64,
+    pub data_size: u64,
+    pub content_type: [u8; 16],
+    pub enc_key_commitment: [u8; 32],
+    pub tags: [u8; 4],
+}
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct HealthStatus {
+    pub pause_flags: u8,
+    pub relayer_count: u8,
+    pub threshold: u8,
+    pub pending_count: u64,
+    pub vault_lamports: u64,
+    pub version: u8,
+}
+/// A delegate's read access to a request.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, InitSpace)]
+pub struct DelegateGrant {
+    pub key: Pubkey,
+    /// When the grant lapses, or zero for never.
+    pub expires_at: i64,
+}
+impl DelegateGrant {
+    pub fn is_active(&self, now: i64) -> bool {
+        self.expires_at == 0 || now < self.expires_at
+    }
+}
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, InitSpace)]
+pub struct TierConfig {
+    /// Floor on the payment, on top of the size-based price.
+    pub min_payment: u64,
+    /// Term used when a request passes a zero `duration_secs`.
+    pub default_duration_secs: i64,
+}
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, InitSpace)]
+pub enum Status {
+    Pending,
+    Confirmed,
+    Failed,
+    Revoked,
+    Expired,
+    Cancelled,
+    /// Opened while paused. Handled like `Pending`, except it can't be
+    /// confirmed until `PAUSE_INITIATE` is lifted.
+    Queued,
+}
+#[derive(Accounts)]
+pub struct Initialize<'info> {
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + State::INIT_SPACE,
+        seeds = [b"state"],
+        bump
+    )]
+    pub state: Account<'info, State>,
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + Vault::INIT_SPACE,
+        seeds = [b"vault"],
+        bump
+    )]
+    pub vault: Account<'info, Vault>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+#[derive(Accounts)]
+#[instruction(data_hash: [u8; 32])]
+pub struct InitiateStorage<'info> {
+    #[account(mut, seeds = [b"state"], bump = state.state_bump)]
+    pub state: Account<'info, State>,
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + UserState::INIT_SPACE,
+        seeds = [b"user", user.key().as_ref()],
+        bump
+    )]
+    pub user_state: Account<'info, UserState>,
+    #[account(
+        init,
+        payer = user,
+        space = 8 + Request::INIT_SPACE,
+        seeds = [b"request", user.key().as_ref(), &user_state.request_count.to_le_bytes()],
+        bump
+    )]
+    pub request: Account<'info, Request>,
+    #[account(
+        init,
+        payer = user,
+        space = 8,
+        seeds = [b"escrow", request.key().as_ref()],
+        bump
+    )]
+    pub escrow: Account<'info, Escrow>,
+    /// CHECK: Only checked for existence, when `dedup` is set.
+    #[account(seeds = [b"hash", data_hash.as_ref()], bump)]
+    pub hash_registry: UncheckedAccount<'info>,
+    #[account(mut, seeds = [b"vault"], bump = state.vault_bump)]
+    pub vault: Account<'info, Vault>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+#[derive(Accounts)]
+pub struct InitiateStorageBatch<'info> {
+    #[account(mut, seeds = [b"state"], bump = state.state_bump)]
+    pub state: Account<'info, State>,
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + UserState::INIT_SPACE,
+        seeds = [b"user", user.key().as_ref()],
+        bump
+    )]
+    pub user_state: Account<'info, UserState>,
+    #[account(mut, seeds = [b"vault"], bump = state.vault_bump)]
+    pub vault: Account<'info, Vault>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+#[derive(Accounts)]
+#[instruction(data_hash: [u8; 32])]
+pub struct InitiateStorageSponsored<'info> {
+    #[account(mut, seeds = [b"state"], bump = state.state_bump)]
+    pub state: Account<'info, State>,
+    #[account(
+        init_if_needed,
+        payer = sponsor,
+        space = 8 + UserState::INIT_SPACE,
+        seeds = [b"user", user.key().as_ref()],
+        bump
+    )]
+    pub user_state: Account<'info, UserState>,
+    #[account(
+        init,
+        payer = sponsor,
+        space = 8 + Request::INIT_SPACE,
+        seeds = [b"request", user.key().as_ref(), &user_state.request_count.to_le_bytes()],
+        bump
+    )]
+    pub request: Account<'info, Request>,
+    #[account(
+        init,
+        payer = sponsor,
+        space = 8,
+        seeds = [b"escrow", request.key().as_ref()],
+        bump
+    )]
+    pub escrow: Account<'info, Escrow>,
+    /// CHECK: Only checked for existence, when `dedup` is set.
+    #[account(seeds = [b"hash", data_hash.as_ref()], bump)]
+    pub hash_registry: UncheckedAccount<'info>,
+    #[account(mut, seeds = [b"vault"], bump = state.vault_bump)]
+    pub vault: Account<'info, Vault>,
+    /// The owner still signs, so nobody can open requests in their name.
+    pub user: Signer<'info>,
+    #[account(mut)]
+    pub sponsor: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+#[derive(Accounts)]
+#[instruction(data_hash: [u8; 32])]
+pub struct InitiateStorageSpl<'info> {
+    #[account(mut, seeds = [b"state"], bump = state.state_bump)]
+    pub state: Account<'info, State>,
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + UserState::INIT_SPACE,
+        seeds = [b"user", user.key().as_ref()],
+        bump
+    )]
+    pub user_state: Account<'info, UserState>,
+    #[account(
+        init,
+        payer = user,
+        space = 8 + Request::INIT_SPACE,
+        seeds = [b"request", user.key().as_ref(), &user_state.request_count.to_le_bytes()],
+        bump
+    )]
+    pub request: Account<'info, Request>,
+    #[account(
+        init,
+        payer = user,
+        space = 8,
+        seeds = [b"escrow", request.key().as_ref()],
+        bump
+    )]
+    pub escrow: Account<'info, Escrow>,
+    /// CHECK: Only checked for existence, when `dedup` is set.
+    #[account(seeds = [b"hash", data_hash.as_ref()], bump)]
+    pub hash_registry: UncheckedAccount<'info>,
+    #[account(address = state.payment_mint @ ErrorCode::InvalidMint)]
+    pub mint: Account<'info, Mint>,
+    #[account(mut, token::mint = mint, token::authority = user)]
+    pub user_token: Account<'info, TokenAccount>,
+    #[account(mut, seeds = [b"vault"], bump = state.vault_bump)]
+    pub vault: Account<'info, Vault>,
+    #[account(mut, token::mint = mint, token::authority = vault)]
+    pub vault_token: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+#[derive(Accounts)]
+pub struct UpdateStatus<'info> {
+    #[account(mut, seeds = [b"state"], bump = state.state_bump)]
+    pub state: Account<'info, State>,
+    #[account(mut)]
+    pub request: Account<'info, Request>,
+    #[account(mut, seeds = [b"user", request.user.as_ref()], bump)]
+    pub user_state: Account<'info, UserState>,
+    #[account(mut, seeds = [b"escrow", request.key().as_ref()], bump)]
+    pub escrow: Account<'info, Escrow>,
+    #[account(mut, seeds = [b"vault"], bump = state.vault_bump)]
+    pub vault: Account<'info, Vault>,
+    #[account(constraint = state.is_relayer(&relayer.key()) @ ErrorCode::Unauthorized)]
+    pub relayer: Signer<'info>,
+    /// CHECK: This is the instruction sysvar account
+    #[account(address = IX_ID)]
+    pub instruction_sysvar: AccountInfo<'info>,
+}
+#[derive(Accounts)]
+pub struct UpdateBlob<'info> {
+    #[account(seeds = [b"state"], bump = state.state_bump)]
+    pub state: Account<'info, State>,
+    #[account(mut)]
+    pub request: Account<'info, Request>,
+    #[account(constraint = state.is_relayer(&relayer.key()) @ ErrorCode::Unauthorized)]
+    pub relayer: Signer<'info>,
+    /// CHECK: This is the instruction sysvar account
+    #[account(address = IX_ID)]
+    pub instruction_sysvar: AccountInfo<'info>,
+}
+#[derive(Accounts)]
+pub struct UpdateStatusBatch<'info> {
+    #[account(mut, seeds = [b"state"], bump = state.state_bump)]
+    pub state: Account<'info, State>,
+    #[account(mut, seeds = [b"vault"], bump = state.vault_bump)]
+    pub vault: Account<'info, Vault>,
+    #[account(constraint = state.is_relayer(&relayer.key()) @ ErrorCode::Unauthorized)]
+    pub relayer: Signer<'info>,
+    /// CHECK: This is the instruction sysvar account
+    #[account(address = IX_ID)]
+    pub instruction_sysvar: AccountInfo<'info>,
+}
+#[derive(Accounts)]
+pub struct MarkFailed<'info> {
+    #[account(mut, seeds = [b"state"], bump = state.state_bump)]
+    pub state: Account<'info, State>,
+    #[account(mut)]
+    pub request: Account<'info, Request>,
+    #[account(mut, seeds = [b"user", request.user.as_ref()], bump)]
+    pub user_state: Account<'info, UserState>,
+    #[account(
+        mut,
+        address = request.refund_address() @ ErrorCode::InvalidRecipient
+    )]
+    pub refund_to: SystemAccount<'info>,
+    #[account(mut, seeds = [b"escrow", request.key().as_ref()], bump)]
+    pub escrow: Account<'info, Escrow>,
+    #[account(mut, seeds = [b"vault"], bump = state.vault_bump)]
+    pub vault: Account<'info, Vault>,
+    #[account(constraint = state.is_relayer(&relayer.key()) @ ErrorCode::Unauthorized)]
+    pub relayer: Signer<'info>,
+}
+#[derive(Accounts)]
+pub struct ForceFail<'info> {
+    #[account(mut, seeds = [b"state"], bump = state.state_bump, has_one = admin)]
+    pub state: Account<'info, State>,
+    #[account(mut)]
+    pub request: Account<'info, Request>,
+    #[account(mut, seeds = [b"user", request.user.as_ref()], bump)]
+    pub user_state: Account<'info, UserState>,
+    #[account(
+        mut,
+        address = request.refund_address() @ ErrorCode::InvalidRecipient
+    )]
+    pub refund_to: SystemAccount<'info>,
+    #[account(mut, seeds = [b"escrow", request.key().as_ref()], bump)]
+    pub escrow: Account<'info, Escrow>,
+    #[account(mut, seeds = [b"vault"], bump = state.vault_bump)]
+    pub vault: Account<'info, Vault>,
+    pub admin: Signer<'info>,
+}
+#[derive(Accounts)]
+pub struct AdminSetStatus<'info> {
+    #[account(mut, seeds = [b"state"], bump = state.state_bump, has_one = admin)]
+    pub state: Account<'info, State>,
+    #[account(mut)]
+    pub request: Account<'info, Request>,
+    pub admin: Signer<'info>,
+}
+#[derive(Accounts)]
+pub struct MarkFailedBatch<'info> {
+    #[account(mut, seeds = [b"state"], bump = state.state_bump)]
+    pub state: Account<'info, State>,
+    #[account(mut, seeds = [b"vault"], bump = state.vault_bump)]
+    pub vault: Account<'info, Vault>,
+    #[account(constraint = state.is_relayer(&relayer.key()) @ ErrorCode::Unauthorized)]
+    pub relayer: Signer<'info>,
+}
+#[derive(Accounts)]
+pub struct MarkFailedSpl<'info> {
+    #[account(mut, seeds = [b"state"], bump = state.state_bump)]
+    pub state: Account<'info, State>,
+    #[account(mut, constraint = !request.is_native() @ ErrorCode::InvalidMint)]
+    pub request: Account<'info, Request>,
+    #[account(mut, seeds = [b"user", request.user.as_ref()], bump)]
+    pub user_state: Account<'info, UserState>,
+    #[account(
+        mut,
+        token::mint = request.payment_mint,
+        token::authority = request.user
+    )]
+    pub user_token: Account<'info, TokenAccount>,
+    #[account(seeds = [b"vault"], bump = state.vault_bump)]
+    pub vault: Account<'info, Vault>,
+    #[account(
+        mut,
+        token::mint = request.payment_mint,
+        token::authority = vault
+    )]
+    pub vault_token: Account<'info, TokenAccount>,
+    #[account(constraint = state.is_relayer(&relayer.key()) @ ErrorCode::Unauthorized)]
+    pub relayer: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+#[derive(Accounts)]
+pub struct TimeoutRequest<'info> {
+    #[account(mut, seeds = [b"state"], bump = state.state_bump)]
+    pub state: Account<'info, State>,
+    #[account(mut)]
+    pub request: Account<'info, Request>,
+    #[account(mut, seeds = [b"user", request.user.as_ref()], bump)]
+    pub user_state: Account<'info, UserState>,
+    #[account(mut, seeds = [b"escrow", request.key().as_ref()], bump)]
+    pub escrow: Account<'info, Escrow>,
+    #[account(
+        mut,
+        address = request.refund_address() @ ErrorCode::InvalidRecipient
+    )]
+    pub refund_to: SystemAccount<'info>,
+}
+#[derive(Accounts)]
+pub struct CancelRequest<'info> {
+    #[account(mut, seeds = [b"state"], bump = state.state_bump)]
+    pub state: Account<'info, State>,
+    #[account(mut, has_one = user @ ErrorCode::Unauthorized)]
+    pub request: Account<'info, Request>,
+    #[account(mut, seeds = [b"user", request.user.as_ref()], bump)]
+    pub user_state: Account<'info, UserState>,
+    #[account(mut, seeds = [b"escrow", request.key().as_ref()], bump)]
+    pub escrow: Account<'info, Escrow>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        address = request.refund_address() @ ErrorCode::InvalidRecipient
+    )]
+    pub refund_to: SystemAccount<'info>,
+}
+#[derive(Accounts)]
+pub struct CancelRequestSpl<'info> {
+    #[account(mut, seeds = [b"state"], bump = state.state_bump)]
+    pub state: Account<'info, State>,
+    #[account(
+        mut,
+        has_one = user @ ErrorCode::Unauthorized,
+        constraint = !request.is_native() @ ErrorCode::InvalidMint
+    )]
+    pub request: Account<'info, Request>,
+    #[account(mut, seeds = [b"user", request.user.as_ref()], bump)]
+    pub user_state: Account<'info, UserState>,
+    #[account(
+        mut,
+        token::mint = request.payment_mint,
+        token::authority = user
+    )]
+    pub user_token: Account<'info, TokenAccount>,
+    #[account(seeds = [b"vault"], bump = state.vault_bump)]
+    pub vault: Account<'info, Vault>,
+    #[account(
+        mut,
+        token::mint = request.payment_mint,
+        token::authority = vault
+    )]
+    pub vault_token: Account<'info, TokenAccount>,
+    pub user: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+#[derive(Accounts)]
+pub struct RetryRequest<'info> {
+    #[account(mut, seeds = [b"state"], bump = state.state_bump)]
+    pub state: Account<'info, State>,
+    #[account(mut, has_one = user @ ErrorCode::Unauthorized)]
+    pub request: Account<'info, Request>,
+    #[account(mut, seeds = [b"user", request.user.as_ref()], bump)]
+    pub user_state: Account<'info, UserState>,
+    #[account(mut, seeds = [b"escrow", request.key().as_ref()], bump)]
+    pub escrow: Account<'info, Escrow>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+#[derive(Accounts)]
+pub struct RenewRequest<'info> {
+    #[account(mut, seeds = [b"state"], bump = state.state_bump)]
+    pub state: Account<'info, State>,
+    #[account(mut, has_one = user @ ErrorCode::Unauthorized)]
+    pub request: Account<'info, Request>,
+    #[account(mut, seeds = [b"vault"], bump = state.vault_bump)]
+    pub vault: Account<'info, Vault>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+#[derive(Accounts)]
+pub struct RevokeAccess<'info> {
+    #[account(mut, seeds = [b"state"], bump = state.state_bump)]
+    pub state: Account<'info, State>,
+    #[account(mut, has_one = user)]
+    pub request: Account<'info, Request>,
+    pub user: Signer<'info>,
+}
+#[derive(Accounts)]
+pub struct RestoreAccess<'info> {
+    #[account(mut, has_one = user @ ErrorCode::Unauthorized)]
+    pub request: Account<'info, Request>,
+    pub user: Signer<'info>,
+}
+#[derive(Accounts)]
+pub struct ManageDelegates<'info> {
+    #[account(mut, has_one = user @ ErrorCode::Unauthorized)]
+    pub request: Account<'info, Request>,
+    pub user: Signer<'info>,
+}
+#[derive(Accounts)]
+pub struct TransferRequest<'info> {
+    #[account(mut, has_one = user @ ErrorCode::Unauthorized)]
+    pub request: Account<'info, Request>,
+    pub user: Signer<'info>,
+}
+#[derive(Accounts)]
+pub struct UpdateMetadata<'info> {
+    #[account(mut, seeds = [b"state"], bump = state.state_bump)]
+    pub state: Account<'info, State>,
+    #[account(mut, has_one = user @ ErrorCode::Unauthorized)]
+    pub request: Account<'info, Request>,
+    #[account(mut, seeds = [b"escrow", request.key().as_ref()], bump)]
+    pub escrow: Account<'info, Escrow>,
+    #[account(
+        mut,
+        address = request.refund_address() @ ErrorCode::InvalidRecipient
+    )]
+    pub refund_to: SystemAccount<'info>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+#[derive(Accounts)]
+pub struct SetTags<'info> {
+    #[account(mut, has_one = user @ ErrorCode::Unauthorized)]
+    pub request: Account<'info, Request>,
+    pub user: Signer<'info>,
+}
+#[derive(Accounts)]
+pub struct SetRefundAddress<'info> {
+    #[account(mut, has_one = user @ ErrorCode::Unauthorized)]
+    pub request: Account<'info, Request>,
+    pub user: Signer<'info>,
+}
+#[derive(Accounts)]
+pub struct ChallengeRequest<'info> {
+    #[account(seeds = [b"state"], bump = state.state_bump)]
+    pub state: Account<'info, State>,
+    #[account(mut, has_one = user @ ErrorCode::Unauthorized)]
+    pub request: Account<'info, Request>,
+    pub user: Signer<'info>,
+}
+/// Answering a challenge only touches the request, so unlike `UpdateStatus`
+/// it needs no owner-derived accounts.
+#[derive(Accounts)]
+pub struct AnswerChallenge<'info> {
+    #[account(seeds = [b"state"], bump = state.state_bump)]
+    pub state: Account<'info, State>,
+    #[account(mut)]
+    pub request: Account<'info, Request>,
+    #[account(constraint = state.is_relayer(&relayer.key()) @ ErrorCode::Unauthorized)]
+    pub relayer: Signer<'info>,
+    /// CHECK: This is the instruction sysvar account
+    #[account(address = IX_ID)]
+    pub instruction_sysvar: AccountInfo<'info>,
+}
+#[derive(Accounts)]
+pub struct FailChallenge<'info> {
+    #[account(mut, seeds = [b"state"], bump = state.state_bump)]
+    pub state: Account<'info, State>,
+    #[account(mut)]
+    pub request: Account<'info, Request>,
+    #[account(mut, seeds = [b"vault"], bump = state.vault_bump)]
+    pub vault: Account<'info, Vault>,
+    #[account(
+        mut,
+        address = request.refund_address() @ ErrorCode::InvalidRecipient
+    )]
+    pub refund_to: SystemAccount<'info>,
+}
+#[derive(Accounts)]
+pub struct ExpireRequest<'info> {
+    #[account(mut)]
+    pub request: Account<'info, Request>,
+}
+#[derive(Accounts)]
+pub struct CloseRequest<'info> {
+    #[account(mut, has_one = user @ ErrorCode::Unauthorized, close = user)]
+    pub request: Account<'info, Request>,
+    #[account(mut, seeds = [b"escrow", request.key().as_ref()], bump, close = user)]
+    pub escrow: Account<'info, Escrow>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+}
+#[derive(Accounts)]
+pub struct RegisterHash<'info> {
+    #[account(constraint = request.status == Status::Confirmed @ ErrorCode::InvalidStatus)]
+    pub request: Account<'info, Request>,
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + HashRegistry::INIT_SPACE,
+        seeds = [b"hash", request.data_hash.as_ref()],
+        bump
+    )]
+    pub hash_registry: Account<'info, HashRegistry>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+#[derive(Accounts)]
+#[instruction(data_hash: [u8; 32])]
+pub struct ReleaseHash<'info> {
+    #[account(mut, seeds = [b"hash", data_hash.as_ref()], bump, close = payer)]
+    pub hash_registry: Account<'info, HashRegistry>,
+    /// CHECK: The registered request, read only while it still exists.
+    #[account(address = hash_registry.request @ ErrorCode::InvalidStatus)]
+    pub request: UncheckedAccount<'info>,
+    #[account(mut, address = hash_registry.payer @ ErrorCode::InvalidRecipient)]
+    pub payer: SystemAccount<'info>,
+}
+#[derive(Accounts)]
+pub struct MigrateState<'info> {
+    /// CHECK: May be too short to deserialize as `State` until migrated. The
+    /// owner, discriminator and admin are checked in the handler.
+    #[account(mut, seeds = [b"state"], bump)]
+    pub state: UncheckedAccount<'info>,
+    /// CHECK: Vaults from older builds carry no data. The owner and
+    /// discriminator are checked in the handler.
+    #[account(mut, seeds = [b"vault"], bump)]
+    pub vault: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+#[derive(Accounts)]
+#[instruction(relayer: Pubkey)]
+pub struct AddRelayer<'info> {
+    #[account(mut, seeds = [b"state"], bump = state.state_bump, has_one = admin)]
+    pub state: Account<'info, State>,
+    pub admin: Signer<'info>,
+    #[account(mut, address = relayer @ ErrorCode::InvalidRelayer)]
+    pub relayer_signer: Signer<'info>,
+    #[account(
+        init,
+        payer = relayer_signer,
+        space = 8 + RelayerBond::INIT_SPACE,
+        seeds = [b"bond", relayer.as_ref()],
+        bump
+    )]
+    pub bond: Account<'info, RelayerBond>,
+    pub system_program: Program<'info, System>,
+}
+#[derive(Accounts)]
+#[instruction(new_relayer: Pubkey)]
+pub struct SetRelayer<'info> {
+    #[account(mut, seeds = [b"state"], bump = state.state_bump, has_one = admin)]
+    pub state: Account<'info, State>,
+    pub admin: Signer<'info>,
+    #[account(mut, address = new_relayer @ ErrorCode::InvalidRelayer)]
+    pub relayer_signer: Signer<'info>,
+    #[account(
+        init_if_needed,
+        payer = relayer_signer,
+        space = 8 + RelayerBond::INIT_SPACE,
+        seeds = [b"bond", new_relayer.as_ref()],
+        bump
+    )]
+    pub bond: Account<'info, RelayerBond>,
+    pub system_program: Program<'info, System>,
+}
+#[derive(Accounts)]
+#[instruction(relayer: Pubkey)]
+pub struct SlashRelayer<'info> {
+    #[account(seeds = [b"state"], bump = state.state_bump, has_one = admin)]
+    pub state: Account<'info, State>,
+    pub admin: Signer<'info>,
+    #[account(mut, seeds = [b"bond", relayer.as_ref()], bump)]
+    pub bond: Account<'info, RelayerBond>,
+    #[account(mut, seeds = [b"vault"], bump = state.vault_bump)]
+    pub vault: Account<'info, Vault>,
+}
+#[derive(Accounts)]
+pub struct WithdrawBond<'info> {
+    #[account(seeds = [b"state"], bump = state.state_bump)]
+    pub state: Account<'info, State>,
+    #[account(mut, seeds = [b"bond", relayer.key().as_ref()], bump, close = relayer)]
+    pub bond: Account<'info, RelayerBond>,
+    #[account(mut)]
+    pub relayer: Signer<'info>,
+}
+#[derive(Accounts)]
+pub struct AdminAction<'info> {
+    #[account(mut, seeds = [b"state"], bump = state.state_bump, has_one = admin)]
+    pub state: Account<'info, State>,
+    pub admin: Signer<'info>,
+}
+#[derive(Accounts)]
+pub struct PauseAction<'info> {
+    #[account(mut, seeds = [b"state"], bump = state.state_bump)]
+    pub state: Account<'info, State>,
+    #[account(
+        constraint = authority.key() == state.admin
+            || authority.key() == state.guardian @ ErrorCode::Unauthorized
+    )]
+    pub authority: Signer<'info>,
+}
+#[derive(Accounts)]
+pub struct AcceptAdmin<'info> {
+    #[account(mut, seeds = [b"state"], bump = state.state_bump)]
+    pub state: Account<'info, State>,
+    #[account(constraint = new_admin.key() == state.pending_admin @ ErrorCode::Unauthorized)]
+    pub new_admin: Signer<'info>,
+}
+#[derive(Accounts)]
+pub struct SetPaymentMint<'info> {
+    #[account(mut, seeds = [b"state"], bump = state.state_bump, has_one = admin)]
+    pub state: Account<'info, State>,
+    pub mint: Account<'info, Mint>,
+    pub admin: Signer<'info>,
+}
+#[derive(Accounts)]
+pub struct Withdraw<'info> {
+    #[account(mut, seeds = [b"state"], bump = state.state_bump, has_one = admin)]
+    pub state: Account<'info, State>,
+    #[account(mut, seeds = [b"vault"], bump = state.vault_bump)]
+    pub vault: Account<'info, Vault>,
+    pub admin: Signer<'info>,
+    #[account(mut, address = state.payout_address() @ ErrorCode::InvalidRecipient)]
+    pub recipient: SystemAccount<'info>,
+}
+#[derive(Accounts)]
+pub struct WithdrawTokens<'info> {
+    #[account(mut, seeds = [b"state"], bump = state.state_bump, has_one = admin)]
+    pub state: Account<'info, State>,
+    #[account(seeds = [b"vault"], bump = state.vault_bump)]
+    pub vault: Account<'info, Vault>,
+    #[account(mut, token::authority = vault)]
+    pub vault_token: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = vault_token.mint,
+        constraint = recipient_token.owner == state.payout_address() @ ErrorCode::InvalidRecipient
+    )]
+    pub recipient_token: Account<'info, TokenAccount>,
+    pub admin: Signer<'info>,
+    pub token_program: Program<'info, Token>,
 }
 #[derive(Accounts)]
-pub struct UpdateStatus<'info> {
-    #[account(seeds = [b"state"], bump)]
+#[instruction(target: Pubkey)]
+pub struct AdminSweep<'info> {
+    #[account(seeds = [b"state"], bump = state.state_bump, has_one = admin)]
     pub state: Account<'info, State>,
-    #[account(mut)]
-    pub request: Account<'info, Request>,
-    #[account(constraint = relayer.key() == state.relayer @ ErrorCode::Unauthorized)]
-    pub relayer: Signer<'info>,
-    /// CHECK: This is the instruction sysvar account
-    #[account(address = IX_ID)]
-    pub instruction_sysvar: AccountInfo<'info>,
+    #[account(mut, seeds = [b"vault"], bump = state.vault_bump)]
+    pub vault: Account<'info, Vault>,
+    /// CHECK: Must be owned by the program without holding a live account,
+    /// which the handler checks.
+    #[account(mut, address = target)]
+    pub target_account: UncheckedAccount<'info>,
+    pub admin: Signer<'info>,
 }
 #[derive(Accounts)]
-pub struct MarkFailed<'info> {
-    #[account(seeds = [b"state"], bump)]
+#[instruction(user: Pubkey, index: u64)]
+pub struct AdminSweepRequest<'info> {
+    #[account(seeds = [b"state"], bump = state.state_bump, has_one = admin)]
     pub state: Account<'info, State>,
-    #[account(mut)]
-    pub request: Account<'info, Request>,
-    #[account(mut, constraint = user.key() == request.user @ ErrorCode::Unauthorized)]
-    pub user: SystemAccount<'info>,
-    #[account(mut, seeds = [b"vault"], bump)]
+    #[account(mut, seeds = [b"vault"], bump = state.vault_bump)]
     pub vault: Account<'info, Vault>,
-    #[account(constraint = relayer.key() == state.relayer @ ErrorCode::Unauthorized)]
-    pub relayer: Signer<'info>,
+    #[account(seeds = [b"user", user.as_ref()], bump)]
+    pub user_state: Account<'info, UserState>,
+    /// CHECK: Only swept once opened, while the System Program owns it with
+    /// no data.
+    #[account(mut, seeds = [b"request", user.as_ref(), &index.to_le_bytes()], bump)]
+    pub request: UncheckedAccount<'info>,
+    /// CHECK: Same as `request`.
+    #[account(mut, seeds = [b"escrow", request.key().as_ref()], bump)]
+    pub escrow: UncheckedAccount<'info>,
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
 }
 #[derive(Accounts)]
-pub struct RevokeAccess<'info> {
-    #[account(mut, has_one = user)]
+pub struct StateView<'info> {
+    #[account(seeds = [b"state"], bump = state.state_bump)]
+    pub state: Account<'info, State>,
+}
+#[derive(Accounts)]
+pub struct RequestView<'info> {
     pub request: Account<'info, Request>,
-    pub user: Signer<'info>,
 }
 #[derive(Accounts)]
-pub struct AdminAction<'info> {
-    #[account(mut, seeds = [b"state"], bump, has_one = admin)]
-    pub state: Account<'info, State>,
-    pub admin: Signer<'info>,
+#[instruction(user: Pubkey)]
+pub struct UserView<'info> {
+    /// CHECK: Only read when initialized, as a `UserState`.
+    #[account(seeds = [b"user", user.as_ref()], bump)]
+    pub user_state: UncheckedAccount<'info>,
 }
 #[derive(Accounts)]
-pub struct Withdraw<'info> {
-    #[account(mut, seeds = [b"state"], bump, has_one = admin)]
+pub struct VaultView<'info> {
+    #[account(seeds = [b"state"], bump = state.state_bump)]
     pub state: Account<'info, State>,
-    #[account(mut, seeds = [b"vault"], bump)]
+    #[account(seeds = [b"vault"], bump = state.vault_bump)]
     pub vault: Account<'info, Vault>,
-    #[account(mut)]
-    pub admin: Signer<'info>,
 }
 #[event]
 pub struct StorageRequested {
@@ -284,6 +3974,15 @@ pub struct StorageRequested {
     pub data_hash: [u8; 32],
     pub payment: u64,
     pub timestamp: i64,
+    pub data_size: u64,
+    pub content_type: [u8; 16],
+    pub enc_key_commitment: [u8; 32],
+    pub vault_balance: u64,
+    pub priority: u8,
+    pub memo: [u8; 32],
+    pub tier: u8,
+    pub network_tag: u8,
+    pub tags: [u8; 4],
 }
 #[event]
 pub struct StorageConfirmed {
@@ -291,21 +3990,803 @@ pub struct StorageConfirmed {
     pub blob_id: [u8; 32],
     pub sui_tx_hash: [u8; 32],
     pub proof_hash: [u8; 32],
+    pub vault_balance: u64,
+    pub sui_checkpoint: u64,
+    pub confirmed_by: Pubkey,
+    pub fee_taken: u64,
+    pub storage_cost: u64,
+    pub network_tag: u8,
+    pub sui_object_ids: [[u8; 32]; 2],
+}
+#[event]
+pub struct BlobUpdated {
+    pub request_id: Pubkey,
+    pub old_blob_id: [u8; 32],
+    pub blob_id: [u8; 32],
+    pub sui_tx_hash: [u8; 32],
+    pub proof_hash: [u8; 32],
+    pub sui_checkpoint: u64,
+    pub sui_object_ids: [[u8; 32]; 2],
+}
+#[event]
+pub struct FeesAccrued {
+    pub request_id: Pubkey,
+    pub fee: u64,
+    pub accrued_fees: u64,
+}
+#[event]
+pub struct StatusChanged {
+    pub request_id: Pubkey,
+    pub old_status: Status,
+    pub new_status: Status,
+    pub timestamp: i64,
+}
+#[event]
+pub struct StatusOverridden {
+    pub request_id: Pubkey,
+    pub admin: Pubkey,
+    pub old_status: Status,
+    pub new_status: Status,
 }
 #[event]
 pub struct RequestFailed {
     pub request_id: Pubkey,
+    pub refunded: u64,
+    pub retained: u64,
+}
+#[event]
+pub struct RequestTimedOut {
+    pub request_id: Pubkey,
+    pub elapsed: i64,
+}
+#[event]
+pub struct RequestCancelled {
+    pub request_id: Pubkey,
+    pub refund: u64,
+}
+#[event]
+pub struct RequestRetried {
+    pub request_id: Pubkey,
+    pub payment: u64,
+}
+#[event]
+pub struct RequestRenewed {
+    pub request_id: Pubkey,
+    pub expiry: i64,
+    pub payment: u64,
+}
+#[event]
+pub struct AccessGranted {
+    pub request_id: Pubkey,
+    pub delegate: Pubkey,
+    /// Zero for a grant that never lapses.
+    pub expires_at: i64,
 }
+/// `delegate` is `None` when the owner revokes the request as a whole.
 #[event]
 pub struct AccessRevoked {
     pub request_id: Pubkey,
+    pub delegate: Option<Pubkey>,
+}
+#[event]
+pub struct AccessRestored {
+    pub request_id: Pubkey,
+}
+#[event]
+pub struct OwnershipTransferred {
+    pub request_id: Pubkey,
+    pub old: Pubkey,
+    pub new: Pubkey,
+}
+#[event]
+pub struct RequestExpired {
+    pub request_id: Pubkey,
+    pub expiry: i64,
+}
+#[event]
+pub struct HashRegistered {
+    pub data_hash: [u8; 32],
+    pub request_id: Pubkey,
+}
+#[event]
+pub struct HashReleased {
+    pub data_hash: [u8; 32],
+    pub request_id: Pubkey,
+}
+#[event]
+pub struct RequestClosed {
+    pub request_id: Pubkey,
+    pub reclaimed: u64,
+}
+#[event]
+pub struct GuardianUpdated {
+    pub old_guardian: Pubkey,
+    pub new_guardian: Pubkey,
+}
+#[event]
+pub struct FeeRecipientUpdated {
+    pub old_recipient: Pubkey,
+    pub new_recipient: Pubkey,
+}
+#[event]
+pub struct AdminTransferred {
+    pub old: Pubkey,
+    pub new: Pubkey,
+}
+#[event]
+pub struct RelayerUpdated {
+    pub old_relayer: Pubkey,
+    pub new_relayer: Pubkey,
+}
+#[event]
+pub struct RelayerAdded {
+    pub relayer: Pubkey,
+}
+#[event]
+pub struct RelayerSlashed {
+    pub relayer: Pubkey,
+    pub amount: u64,
+    pub remaining: u64,
+}
+#[event]
+pub struct BondWithdrawn {
+    pub relayer: Pubkey,
+    pub amount: u64,
+}
+#[event]
+pub struct MaxDataSizeUpdated {
+    pub max_data_size: u64,
+}
+#[event]
+pub struct MinRequestIntervalUpdated {
+    pub min_request_interval: i64,
+}
+#[event]
+pub struct AllowedContentTypesUpdated {
+    pub content_types: [[u8; 16]; MAX_CONTENT_TYPES],
+}
+#[event]
+pub struct QueueModeUpdated {
+    pub enabled: bool,
+}
+#[event]
+pub struct RelayerBondUpdated {
+    pub relayer_bond: u64,
+}
+#[event]
+pub struct RelayerRemoved {
+    pub relayer: Pubkey,
+}
+#[event]
+pub struct MinPaymentUpdated {
+    pub old_min: u64,
+    pub new_min: u64,
+}
+#[event]
+pub struct MaxPaymentUpdated {
+    pub old_max: u64,
+    pub new_max: u64,
+}
+#[event]
+pub struct CreationFeeUpdated {
+    pub creation_fee: u64,
+}
+#[event]
+pub struct BreakerThresholdUpdated {
+    pub threshold: u16,
+    pub window_secs: i64,
+}
+#[event]
+pub struct CircuitBreakerTripped {
+    pub failures: u16,
+    pub window_secs: i64,
+    pub timestamp: i64,
+}
+#[event]
+pub struct Configured {
+    pub params: ConfigParams,
+}
+#[event]
+pub struct StateMigrated {
+    pub version: u8,
+}
+#[event]
+pub struct Paused {
+    /// The admin or guardian that paused.
+    pub admin: Pubkey,
+    pub timestamp: i64,
+}
+#[event]
+pub struct PauseFlagsUpdated {
+    /// The admin or guardian, or the default key for a circuit-breaker trip.
+    pub authority: Pubkey,
+    pub old_flags: u8,
+    pub new_flags: u8,
+    pub timestamp: i64,
+}
+#[event]
+pub struct Unpaused {
+    pub admin: Pubkey,
+    pub timestamp: i64,
+}
+#[event]
+pub struct RequestChallenged {
+    pub request_id: Pubkey,
+    pub deadline: i64,
+}
+#[event]
+pub struct ChallengeAnswered {
+    pub request_id: Pubkey,
+    pub blob_id: [u8; 32],
+    pub relayer: Pubkey,
+}
+#[event]
+pub struct ChallengeFailed {
+    pub request_id: Pubkey,
+    pub refunded: u64,
+}
+#[event]
+pub struct ChallengeWindowUpdated {
+    pub challenge_window_secs: i64,
+}
+#[event]
+pub struct MetadataUpdated {
+    pub request_id: Pubkey,
+    pub content_type: [u8; 16],
+    pub data_size: u64,
+    pub payment: u64,
+}
+#[event]
+pub struct TagsUpdated {
+    pub request_id: Pubkey,
+    pub tags: [u8; 4],
+}
+#[event]
+pub struct RefundAddressUpdated {
+    pub request_id: Pubkey,
+    pub refund_to: Pubkey,
+}
+#[event]
+pub struct TierConfigUpdated {
+    pub tier: u8,
+    pub min_payment: u64,
+    pub default_duration_secs: i64,
+}
+#[event]
+pub struct PricingUpdated {
+    pub base_fee: u64,
+    pub per_byte_fee: u64,
+}
+#[event]
+pub struct ThresholdUpdated {
+    pub threshold: u8,
+}
+#[event]
+pub struct RelayerEthAddressUpdated {
+    pub eth_address: [u8; 20],
+}
+#[event]
+pub struct PaymentMintUpdated {
+    pub mint: Pubkey,
+}
+#[event]
+pub struct FeeUpdated {
+    pub fee_bps: u16,
+}
+#[event]
+pub struct FailureFeeUpdated {
+    pub failure_fee_bps: u16,
+}
+#[event]
+pub struct TimeoutUpdated {
+    pub timeout_secs: i64,
+}
+#[event]
+pub struct MaxPendingUpdated {
+    pub max_pending_per_user: u16,
+}
+#[event]
+pub struct ConfirmWindowUpdated {
+    pub confirm_window_secs: i64,
+}
+#[event]
+pub struct WithdrawDelayUpdated {
+    pub delay_secs: i64,
+}
+#[event]
+pub struct WithdrawQueued {
+    pub amount: u64,
+    pub unlock_time: i64,
+}
+#[event]
+pub struct WithdrawDelayQueued {
+    pub delay_secs: i64,
+    pub unlock_time: i64,
+}
+#[event]
+pub struct FeeRecipientQueued {
+    pub new_recipient: Pubkey,
+    pub unlock_time: i64,
+}
+#[event]
+pub struct TokenWithdrawQueued {
+    pub amount: u64,
+    pub unlock_time: i64,
+}
+#[event]
+pub struct TokensWithdrawn {
+    pub admin: Pubkey,
+    pub recipient: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+}
+#[event]
+pub struct Withdrawn {
+    pub admin: Pubkey,
+    pub recipient: Pubkey,
+    pub amount: u64,
+    pub vault_balance_after: u64,
+}
+#[event]
+pub struct EmergencyWithdraw {
+    pub admin: Pubkey,
+    pub recipient: Pubkey,
+    pub amount: u64,
+}
+#[event]
+pub struct DustSwept {
+    pub target: Pubkey,
+    pub amount: u64,
+}
+#[event]
+pub struct FeesWithdrawn {
+    pub admin: Pubkey,
+    pub recipient: Pubkey,
+    pub amount: u64,
+    pub accrued_fees_after: u64,
+    pub vault_balance_after: u64,
 }
 #[error_code]
 pub enum ErrorCode {
+    #[msg("The program is currently paused")]
     Paused,
+    #[msg("Payment is below the required amount")]
     InsufficientPayment,
+    #[msg("The request is not in a valid status for this action")]
     InvalidStatus,
+    #[msg("Signer is not authorized for this action")]
     Unauthorized,
+    #[msg("Missing or invalid relayer signature")]
     InvalidSignature,
+    #[msg("Invalid admin address")]
+    InvalidAdmin,
+    #[msg("Invalid relayer address")]
+    InvalidRelayer,
+    #[msg("Relayer is already enrolled")]
+    DuplicateRelayer,
+    #[msg("The relayer list is full")]
+    RelayerListFull,
+    #[msg("Relayer is not enrolled")]
+    RelayerNotFound,
+    #[msg("Amount must be greater than zero")]
+    InvalidAmount,
+    #[msg("The vault balance is too low")]
+    InsufficientVaultBalance,
+    #[msg("Arithmetic overflow")]
+    ArithmeticOverflow,
+    #[msg("The payer has insufficient lamports")]
+    InsufficientFunds,
+    #[msg("Invalid duration")]
+    InvalidDuration,
+    #[msg("The request has not expired yet")]
+    NotExpired,
+    #[msg("The request has not timed out yet")]
+    TimeoutNotReached,
+    #[msg("The request's payment mint doesn't match this instruction")]
+    InvalidMint,
+    #[msg("Basis points must not exceed 10000")]
+    InvalidFeeBps,
+    #[msg("Not enough accrued fees")]
+    InsufficientFees,
+    #[msg("Content type must be set")]
+    InvalidContentType,
+    #[msg("Invalid delegate address")]
+    InvalidDelegate,
+    #[msg("Delegate already has access")]
+    DuplicateDelegate,
+    #[msg("The delegate list is full")]
+    DelegateListFull,
+    #[msg("Delegate not found")]
+    DelegateNotFound,
+    #[msg("Invalid owner address")]
+    InvalidOwner,
+    #[msg("Batch size or accounts are invalid")]
+    InvalidBatch,
+    #[msg("Threshold must stay between 1 and the relayer count")]
+    InvalidThreshold,
+    #[msg("Not enough relayer signatures")]
+    ThresholdNotMet,
+    #[msg("The signature deadline has passed")]
+    SignatureExpired,
+    #[msg("Data hash must be set")]
+    InvalidDataHash,
+    #[msg("Unknown pause flags")]
+    InvalidPauseFlags,
+    #[msg("No matching withdrawal is queued")]
+    WithdrawNotQueued,
+    #[msg("The queued withdrawal is still timelocked")]
+    WithdrawLocked,
+    #[msg("The request is not yet within its renewal window")]
+    RenewalTooEarly,
+    #[msg("Proof hash doesn't match the blob id and Sui transaction")]
+    InvalidProof,
+    #[msg("The user has too many pending requests")]
+    TooManyPendingRequests,
+    #[msg("The request can no longer be confirmed")]
+    ConfirmWindowElapsed,
+    #[msg("A request for this data hash is already confirmed")]
+    DuplicateDataHash,
+    #[msg("Priority is out of range")]
+    InvalidPriority,
+    #[msg("New requests must be paused first")]
+    NotPaused,
+    #[msg("The quoted price exceeds the maximum acceptable payment")]
+    PaymentAboveMax,
+    #[msg("Invalid recipient address")]
+    InvalidRecipient,
+    #[msg("Payment exceeds the maximum allowed")]
+    PaymentTooLarge,
+    #[msg("The request only accepts its approved relayer")]
+    RelayerNotApproved,
+    #[msg("Unknown service tier")]
+    InvalidTier,
+    #[msg("The vault no longer covers its rent and accrued fees")]
+    VaultInvariantViolated,
+    #[msg("Account is not a valid program account")]
+    InvalidStateAccount,
+    #[msg("State is already at the current version")]
+    AlreadyMigrated,
+    #[msg("The storage term has ended")]
+    StorageExpired,
+    #[msg("Account isn't a dead program account")]
+    InvalidSweepTarget,
+    #[msg("Expiry must be in the future")]
+    InvalidExpiry,
+    #[msg("The relayer's bond is too small")]
+    InsufficientBond,
+    #[msg("Remove the relayer before withdrawing its bond")]
+    RelayerStillActive,
+    #[msg("Data size exceeds the maximum allowed")]
+    DataTooLarge,
+    #[msg("Challenges are disabled")]
+    ChallengesDisabled,
+    #[msg("The challenge deadline has passed")]
+    ChallengeExpired,
+    #[msg("The challenge can still be answered")]
+    ChallengeOpen,
+    #[msg("That status change moves funds and can't be forced")]
+    InvalidStatusOverride,
+    #[msg("Requests are being submitted too quickly")]
+    RateLimited,
+    #[msg("The content type isn't on the allowlist")]
+    UnsupportedContentType,
+    #[msg("The request has an open challenge")]
+    ChallengePending,
+    #[msg("The minimum payment is above the maximum payment")]
+    InvalidPaymentBounds,
+    #[msg("No change is queued")]
+    ChangeNotQueued,
+    #[msg("The queued change is still timelocked")]
+    ChangeLocked,
+}
+// A legacy `paused: bool` of `true` decodes as `PAUSE_INITIATE`, which is
+// exactly what it used to block.
+pub const PAUSE_INITIATE: u8 = 1 << 0;
+pub const PAUSE_CONFIRM: u8 = 1 << 1;
+pub const PAUSE_FAIL: u8 = 1 << 2;
+pub const PAUSE_WITHDRAW: u8 = 1 << 3;
+pub const PAUSE_ALL: u8 = PAUSE_INITIATE | PAUSE_CONFIRM | PAUSE_FAIL | PAUSE_WITHDRAW;
+const MAX_RELAYERS: usize = 5;
+const MAX_DELEGATES: usize = 8;
+const MAX_BATCH_SIZE: usize = 10;
+const DEFAULT_TIMEOUT_SECS: i64 = 24 * 60 * 60;
+const DEFAULT_MIN_PAYMENT: u64 = 1_000_000;
+const DEFAULT_MAX_PAYMENT: u64 = 1_000 * LAMPORTS_PER_SOL;
+const MAX_BPS: u16 = 10_000;
+const RENEWAL_WINDOW_SECS: i64 = 7 * 24 * 60 * 60;
+const RECEIPT_DOMAIN: &[u8] = b"DataHaven:receipt:v2";
+const BLOB_UPDATE_DOMAIN: &[u8] = b"DataHaven:blob-update:v1";
+const MERKLE_ROOT_DOMAIN: &[u8] = b"DataHaven:merkle-root:v1";
+const MAX_MERKLE_DEPTH: usize = 32;
+const MAX_PRIORITY: u8 = 3;
+const MAX_TIERS: usize = 4;
+const MAX_CONTENT_TYPES: usize = 8;
+const STATE_VERSION: u8 = 3;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::solana_program::program_stubs::{set_syscall_stubs, SyscallStubs};
+
+    /// Serves `Rent::default()` to `Rent::get()` off-chain.
+    struct RentStubs;
+    impl SyscallStubs for RentStubs {
+        fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+            unsafe { *(var_addr as *mut Rent) = Rent::default() };
+            0
+        }
+    }
+
+    fn zeroed_state() -> State {
+        State::deserialize(&mut &vec![0u8; State::INIT_SPACE][..]).unwrap()
+    }
+
+    fn receipt() -> Receipt {
+        let (blob_id, sui_tx_hash) = ([1u8; 32], [2u8; 32]);
+        Receipt {
+            blob_id,
+            sui_tx_hash,
+            proof_hash: keccak::hashv(&[&blob_id, &sui_tx_hash]).0,
+            sui_checkpoint: 7,
+            deadline: 100,
+            sui_object_ids: [[3u8; 32], [4u8; 32]],
+        }
+    }
+
+    /// A single-signature Ed25519 verify instruction laid out the way the
+    /// runtime's own builder does: header, public key, signature, message.
+    fn ed25519_ix(signer: &Pubkey, message: &[u8; 32], ix_index: u16) -> Instruction {
+        let (pk_at, sig_at, msg_at) = (16u16, 48u16, 112u16);
+        let mut data = vec![1u8, 0];
+        for field in [sig_at, ix_index, pk_at, ix_index, msg_at, 32, ix_index] {
+            data.extend_from_slice(&field.to_le_bytes());
+        }
+        data.extend_from_slice(signer.as_ref());
+        data.extend_from_slice(&[9u8; 64]);
+        data.extend_from_slice(message);
+        Instruction::new_with_bytes(ed25519_program::ID, &data, vec![])
+    }
+
+    /// A single-signature Secp256k1 verify instruction: header, eth address,
+    /// signature and recovery id, message.
+    fn secp256k1_ix(eth_address: &[u8; 20], message: &[u8; 32], ix_index: u8) -> Instruction {
+        let (eth_at, sig_at, msg_at) = (12u16, 32u16, 97u16);
+        let mut data = vec![1u8];
+        data.extend_from_slice(&sig_at.to_le_bytes());
+        data.push(ix_index);
+        data.extend_from_slice(&eth_at.to_le_bytes());
+        data.push(ix_index);
+        data.extend_from_slice(&msg_at.to_le_bytes());
+        data.extend_from_slice(&32u16.to_le_bytes());
+        data.push(ix_index);
+        data.extend_from_slice(eth_address);
+        data.extend_from_slice(&[9u8; 65]);
+        data.extend_from_slice(message);
+        Instruction::new_with_bytes(secp256k1_program::ID, &data, vec![])
+    }
+
+    #[test]
+    fn required_payment_is_priced_by_size_and_floored() {
+        let mut state = zeroed_state();
+        state.base_fee = 100;
+        state.per_byte_fee = 2;
+        assert_eq!(state.required_payment(50).unwrap(), 200);
+        state.min_payment = 1_000;
+        assert_eq!(state.required_payment(50).unwrap(), 1_000);
+        state.tiers[1].min_payment = 5_000;
+        assert_eq!(state.tier_payment(1, 50).unwrap(), 5_000);
+        assert_eq!(
+            state.tier_payment(MAX_TIERS as u8, 50).unwrap_err(),
+            ErrorCode::InvalidTier.into()
+        );
+        state.per_byte_fee = u64::MAX;
+        assert_eq!(
+            state.required_payment(2).unwrap_err(),
+            ErrorCode::ArithmeticOverflow.into()
+        );
+    }
+
+    #[test]
+    fn migration_fills_defaults_a_v1_state_predates() {
+        // A v1 account: the baseline fields set, everything newer zero.
+        let mut state = zeroed_state();
+        state.relayer = Pubkey::new_unique();
+        state.base_fee = 10;
+        state.fill_migration_defaults();
+        assert_eq!(state.active_relayers(), &[state.relayer]);
+        assert_eq!(state.threshold, 1);
+        assert_eq!(state.timeout_secs, DEFAULT_TIMEOUT_SECS);
+        assert_eq!(state.min_payment, DEFAULT_MIN_PAYMENT);
+        assert_eq!(state.max_payment, DEFAULT_MAX_PAYMENT);
+        assert_eq!(state.base_fee, 10);
+
+        // Values an operator already set survive a re-run.
+        state.min_payment = 5;
+        state.timeout_secs = 60;
+        state.fill_migration_defaults();
+        assert_eq!((state.min_payment, state.timeout_secs), (5, 60));
+        assert_eq!(state.relayer_count, 1);
+    }
+
+    #[test]
+    fn bps_of_rounds_down_without_overflowing() {
+        assert_eq!(bps_of(1_000, 0), 0);
+        assert_eq!(bps_of(1_000, MAX_BPS), 1_000);
+        assert_eq!(bps_of(1_000, 250), 25);
+        assert_eq!(bps_of(999, 1), 0);
+        assert_eq!(bps_of(u64::MAX, MAX_BPS), u64::MAX);
+    }
+
+    #[test]
+    fn renewal_payment_is_pro_rata_rounded_up() {
+        let mut state = zeroed_state();
+        state.base_fee = 1_000;
+        assert_eq!(state.renewal_payment(0, 100, 50).unwrap(), 500);
+        assert_eq!(state.renewal_payment(0, 3, 1).unwrap(), 334);
+        assert_eq!(state.renewal_payment(0, 100, 200).unwrap(), 2_000);
+        for (term, additional) in [(0, 10), (10, 0), (-1, 10)] {
+            assert_eq!(
+                state.renewal_payment(0, term, additional).unwrap_err(),
+                ErrorCode::InvalidDuration.into()
+            );
+        }
+    }
+
+    #[test]
+    fn receipt_message_matches_documented_preimage() {
+        let request = Pubkey::new_unique();
+        let receipt = receipt();
+        let expected = keccak::hashv(&[
+            RECEIPT_DOMAIN,
+            crate::ID.as_ref(),
+            request.as_ref(),
+            &receipt.blob_id,
+            &receipt.sui_tx_hash,
+            &receipt.proof_hash,
+            &7u64.to_le_bytes(),
+            &5u64.to_le_bytes(),
+            &100i64.to_le_bytes(),
+            &[3u8; 32],
+            &[4u8; 32],
+        ])
+        .0;
+        assert_eq!(build_receipt_message(&request, &receipt, 5), expected);
+    }
+
+    #[test]
+    fn receipt_message_binds_every_input() {
+        let request = Pubkey::new_unique();
+        let base = build_receipt_message(&request, &receipt(), 0);
+        assert_ne!(build_receipt_message(&request, &receipt(), 1), base);
+        assert_ne!(build_receipt_message(&Pubkey::new_unique(), &receipt(), 0), base);
+        assert_ne!(build_blob_update_message(&request, &receipt(), 0), base);
+        let mut swapped = receipt();
+        swapped.sui_object_ids.swap(0, 1);
+        assert_ne!(build_receipt_message(&request, &swapped, 0), base);
+    }
+
+    #[test]
+    fn receipt_check_rejects_bad_proof_and_late_submission() {
+        assert!(receipt().check(100).is_ok());
+        assert_eq!(
+            receipt().check(101).unwrap_err(),
+            ErrorCode::SignatureExpired.into()
+        );
+        let mut mangled = receipt();
+        mangled.blob_id = [5u8; 32];
+        assert_eq!(mangled.check(0).unwrap_err(), ErrorCode::InvalidProof.into());
+    }
+
+    #[test]
+    fn merkle_root_hashes_sorted_pairs() {
+        let (leaf, a, b) = ([1u8; 32], [2u8; 32], [0u8; 32]);
+        assert_eq!(merkle_root(leaf, &[]), leaf);
+        let level1 = keccak::hashv(&[&leaf, &a]).0;
+        assert_eq!(merkle_root(leaf, &[a]), level1);
+        // The smaller node always goes first, wherever the leaf sits.
+        assert_eq!(merkle_root(a, &[leaf]), level1);
+        let root = keccak::hashv(&[&b, &level1]).0;
+        assert_eq!(merkle_root(leaf, &[a, b]), root);
+        assert_ne!(merkle_root(leaf, &[b, a]), root);
+    }
+
+    #[test]
+    fn ed25519_attestation_parses_single_local_signature() {
+        let (signer, message) = (Pubkey::new_unique(), [7u8; 32]);
+        for index in [u16::MAX, 2] {
+            assert_eq!(
+                ed25519_attestation(&ed25519_ix(&signer, &message, index), 2),
+                Some((signer, message))
+            );
+        }
+    }
+
+    #[test]
+    fn ed25519_attestation_rejects_malformed_instructions() {
+        let (signer, message) = (Pubkey::new_unique(), [7u8; 32]);
+        // Offsets pointing into another instruction.
+        assert_eq!(ed25519_attestation(&ed25519_ix(&signer, &message, 1), 2), None);
+        let mut ix = ed25519_ix(&signer, &message, u16::MAX);
+        ix.program_id = secp256k1_program::ID;
+        assert_eq!(ed25519_attestation(&ix, 0), None);
+        let mut ix = ed25519_ix(&signer, &message, u16::MAX);
+        ix.data[0] = 2;
+        assert_eq!(ed25519_attestation(&ix, 0), None);
+        let mut ix = ed25519_ix(&signer, &message, u16::MAX);
+        ix.data[12] = 31;
+        assert_eq!(ed25519_attestation(&ix, 0), None);
+        let mut ix = ed25519_ix(&signer, &message, u16::MAX);
+        ix.data.truncate(130);
+        assert_eq!(ed25519_attestation(&ix, 0), None);
+        assert_eq!(
+            ed25519_attestation(&Instruction::new_with_bytes(ed25519_program::ID, &[1], vec![]), 0),
+            None
+        );
+    }
+
+    #[test]
+    fn secp256k1_attestation_parses_single_local_signature() {
+        let (eth_address, message) = ([8u8; 20], [7u8; 32]);
+        assert_eq!(
+            secp256k1_attestation(&secp256k1_ix(&eth_address, &message, 3), 3),
+            Some((eth_address, message))
+        );
+    }
+
+    #[test]
+    fn secp256k1_attestation_rejects_malformed_instructions() {
+        let (eth_address, message) = ([8u8; 20], [7u8; 32]);
+        assert_eq!(secp256k1_attestation(&secp256k1_ix(&eth_address, &message, 1), 3), None);
+        let mut ix = secp256k1_ix(&eth_address, &message, 0);
+        ix.program_id = ed25519_program::ID;
+        assert_eq!(secp256k1_attestation(&ix, 0), None);
+        let mut ix = secp256k1_ix(&eth_address, &message, 0);
+        ix.data[0] = 0;
+        assert_eq!(secp256k1_attestation(&ix, 0), None);
+        let mut ix = secp256k1_ix(&eth_address, &message, 0);
+        ix.data[9] = 20;
+        assert_eq!(secp256k1_attestation(&ix, 0), None);
+        let mut ix = secp256k1_ix(&eth_address, &message, 0);
+        ix.data.truncate(100);
+        assert_eq!(secp256k1_attestation(&ix, 0), None);
+    }
+
+    #[test]
+    fn status_overrides_only_allow_fund_neutral_moves() {
+        use Status::*;
+        let statuses = [Pending, Confirmed, Failed, Revoked, Expired, Cancelled, Queued];
+        let allowed = [
+            (Pending, Queued),
+            (Queued, Pending),
+            (Confirmed, Revoked),
+            (Confirmed, Expired),
+            (Revoked, Confirmed),
+            (Revoked, Expired),
+            (Expired, Confirmed),
+        ];
+        for from in &statuses {
+            for to in &statuses {
+                let expected = allowed.iter().any(|(f, t)| f == from && t == to);
+                assert_eq!(status_override_allowed(from, to), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn vault_invariant_covers_rent_and_accrued_fees() {
+        set_syscall_stubs(Box::new(RentStubs));
+        let mut state = zeroed_state();
+        state.accrued_fees = 5_000;
+        let (key, owner) = (Pubkey::new_unique(), crate::ID);
+        let mut data = vec![0u8; 8 + Vault::INIT_SPACE];
+        let floor = Rent::default().minimum_balance(data.len()) + 5_000;
+        let mut lamports = floor;
+        let vault = AccountInfo::new(&key, false, true, &mut lamports, &mut data, &owner, false, 0);
+        assert!(check_vault_invariant(&state, &vault).is_ok());
+        **vault.lamports.borrow_mut() = floor - 1;
+        assert_eq!(
+            check_vault_invariant(&state, &vault).unwrap_err(),
+            ErrorCode::VaultInvariantViolated.into()
+        );
+    }
 }
-const MIN_PAYMENT: u64 = 1_000_000;
\ No newline at end of file
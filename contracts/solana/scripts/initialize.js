@@ -16,8 +16,15 @@ async function main() {
     program.programId
   );
 
+  // The relayer hot key defaults to the admin wallet.
+  const relayer = process.env.RELAYER_PUBKEY
+    ? new PublicKey(process.env.RELAYER_PUBKEY)
+    : provider.wallet.publicKey;
+  // Echoed in request events so one indexer can serve several deployments.
+  const networkTag = Number(process.env.NETWORK_TAG ?? 0);
+
   const tx = await program.methods
-    .initialize()
+    .initialize(relayer, networkTag)
     .accounts({
       state: statePDA,
       admin: provider.wallet.publicKey,
@@ -26,12 +33,34 @@ async function main() {
     .rpc();
 
   console.log("✅ Initialized. Transaction:", tx);
+
+  const configureTx = await program.methods
+    .configure({
+      minPayment: new anchor.BN(1_000_000),
+      maxPayment: null,
+      baseFee: null,
+      perByteFee: null,
+      feeBps: null,
+      failureFeeBps: null,
+      timeoutSecs: null,
+      confirmWindowSecs: null,
+      maxPendingPerUser: null,
+      maxDataSize: null,
+    })
+    .accounts({
+      state: statePDA,
+      admin: provider.wallet.publicKey,
+    })
+    .rpc();
+
+  console.log("✅ Configured. Transaction:", configureTx);
   console.log("State PDA:", statePDA.toString());
 
   const deploymentInfo = {
     network: "devnet", // or mainnet
     programId: program.programId.toString(),
     statePDA: statePDA.toString(),
+    networkTag,
     admin: provider.wallet.publicKey.toString(),
     timestamp: new Date().toISOString(),
   };
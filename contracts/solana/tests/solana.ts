@@ -1,16 +1,2545 @@
 import * as anchor from "@coral-xyz/anchor";
 import { Program } from "@coral-xyz/anchor";
+import {
+  createMint,
+  getAccount,
+  getOrCreateAssociatedTokenAccount,
+  mintTo,
+  TOKEN_PROGRAM_ID,
+} from "@solana/spl-token";
+import { secp256k1 } from "@noble/curves/secp256k1";
+import { keccak_256 } from "@noble/hashes/sha3";
+import { expect } from "chai";
 import { Solana } from "../target/types/solana";
 
+const { BN } = anchor;
+const {
+  Ed25519Program,
+  Keypair,
+  LAMPORTS_PER_SOL,
+  PublicKey,
+  Secp256k1Program,
+  SystemProgram,
+  SYSVAR_INSTRUCTIONS_PUBKEY,
+  Transaction,
+} = anchor.web3;
+
+const RECEIPT_DOMAIN = Buffer.from("DataHaven:receipt:v2");
+const BLOB_UPDATE_DOMAIN = Buffer.from("DataHaven:blob-update:v1");
+const MERKLE_ROOT_DOMAIN = Buffer.from("DataHaven:merkle-root:v1");
+const PAUSE_INITIATE = 1;
+// Regression guard for the single-signature `verify_receipt` path.
+const VERIFY_CU_BUDGET = 80_000;
+const DAY = 24 * 60 * 60;
+
 describe("solana", () => {
   // Configure the client to use the local cluster.
   anchor.setProvider(anchor.AnchorProvider.env());
 
+  const provider = anchor.getProvider() as anchor.AnchorProvider;
+  const connection = provider.connection;
   const program = anchor.workspace.solana as Program<Solana>;
+  const admin = (provider.wallet as anchor.Wallet).payer;
+  // `initialize` enrolls the admin wallet as the first relayer.
+  const relayer = admin;
+
+  const pda = (...seeds: (Buffer | Uint8Array)[]) =>
+    PublicKey.findProgramAddressSync(seeds, program.programId)[0];
+  const statePda = pda(Buffer.from("state"));
+  const vaultPda = pda(Buffer.from("vault"));
+  const userStatePda = (user: anchor.web3.PublicKey) =>
+    pda(Buffer.from("user"), user.toBuffer());
+  const requestPda = (user: anchor.web3.PublicKey, index: number) =>
+    pda(
+      Buffer.from("request"),
+      user.toBuffer(),
+      new BN(index).toArrayLike(Buffer, "le", 8)
+    );
+  const escrowPda = (request: anchor.web3.PublicKey) =>
+    pda(Buffer.from("escrow"), request.toBuffer());
+  const hashPda = (dataHash: number[]) =>
+    pda(Buffer.from("hash"), Buffer.from(dataHash));
+
+  const bytes = (len: number, fill: number) => Array(len).fill(fill);
+  const contentType = (mime: string) => {
+    const out = Buffer.alloc(16);
+    out.write(mime);
+    return [...out];
+  };
+  const randomHash = () => [...Keypair.generate().publicKey.toBuffer()];
+  const sleep = (ms: number) => new Promise((r) => setTimeout(r, ms));
+
+  async function funded(sol = 10) {
+    const kp = Keypair.generate();
+    const sig = await connection.requestAirdrop(
+      kp.publicKey,
+      sol * LAMPORTS_PER_SOL
+    );
+    await connection.confirmTransaction(sig, "confirmed");
+    return kp;
+  }
+
+  async function expectErr(promise: Promise<unknown>, code: string) {
+    let failed = false;
+    try {
+      await promise;
+    } catch (err) {
+      failed = true;
+      const logs = ((err as any).logs ?? []).join("\n");
+      expect(`${err}\n${logs}`).to.include(code);
+    }
+    expect(failed, `expected ${code}`).to.be.true;
+  }
+
+  async function eventsOf(sig: string) {
+    await connection.confirmTransaction(sig, "confirmed");
+    const tx = await connection.getTransaction(sig, {
+      commitment: "confirmed",
+      maxSupportedTransactionVersion: 0,
+    });
+    const parser = new anchor.EventParser(program.programId, program.coder);
+    return [...parser.parseLogs(tx.meta.logMessages)];
+  }
+  const named = (events: { name: string }[], name: string) =>
+    events.filter((e) => e.name.toLowerCase() === name.toLowerCase());
+
+  const isRelayer = (key: anchor.web3.PublicKey) =>
+    program.methods
+      .isRelayer(key)
+      .accountsPartial({ state: statePda })
+      .view() as Promise<boolean>;
+
+  const bondPda = (relayer: anchor.web3.PublicKey) =>
+    pda(Buffer.from("bond"), relayer.toBuffer());
+  async function enrollRelayer() {
+    const relayer = await funded(2);
+    await program.methods
+      .addRelayer(relayer.publicKey)
+      .accountsPartial({
+        state: statePda,
+        admin: admin.publicKey,
+        relayerSigner: relayer.publicKey,
+        bond: bondPda(relayer.publicKey),
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([relayer])
+      .rpc({ commitment: "confirmed" });
+    return relayer;
+  }
+  const removeRelayer = (relayer: anchor.web3.PublicKey) =>
+    program.methods
+      .removeRelayer(relayer)
+      .accountsPartial({ state: statePda, admin: admin.publicKey })
+      .rpc({ commitment: "confirmed" });
+
+  // Terms for an `initiate_*` call, in instruction argument order.
+  function requestTerms(overrides: Record<string, any> = {}) {
+    return {
+      dataHash: randomHash(),
+      paymentAmount: new BN(2_000_000),
+      dataSize: new BN(1_024),
+      durationSecs: new BN(30 * DAY),
+      contentType: contentType("image/png"),
+      encKeyCommitment: bytes(32, 0),
+      dedup: false,
+      priority: 0,
+      maxAcceptablePayment: null,
+      memo: bytes(32, 0),
+      approvedRelayer: PublicKey.default,
+      tier: 0,
+      refundTo: PublicKey.default,
+      tags: [1, 2, 3, 4],
+      ...overrides,
+    };
+  }
+
+  async function nextIndex(user: anchor.web3.PublicKey) {
+    const userState = await program.account.userState.fetchNullable(
+      userStatePda(user)
+    );
+    return userState ? userState.requestCount.toNumber() : 0;
+  }
+
+  async function openRequest(
+    user: anchor.web3.Keypair,
+    overrides: Record<string, any> = {}
+  ) {
+    const index = await nextIndex(user.publicKey);
+    const request = requestPda(user.publicKey, index);
+    const escrow = escrowPda(request);
+    const terms = requestTerms(overrides);
+    const sig = await program.methods
+      .initiateStorage(
+        terms.dataHash,
+        terms.paymentAmount,
+        terms.dataSize,
+        terms.durationSecs,
+        terms.contentType,
+        terms.encKeyCommitment,
+        terms.dedup,
+        terms.priority,
+        terms.maxAcceptablePayment,
+        terms.memo,
+        terms.approvedRelayer,
+        terms.tier,
+        terms.refundTo,
+        terms.tags
+      )
+      .accountsPartial({
+        state: statePda,
+        userState: userStatePda(user.publicKey),
+        request,
+        escrow,
+        hashRegistry: hashPda(terms.dataHash),
+        vault: vaultPda,
+        user: user.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user])
+      .rpc({ commitment: "confirmed" });
+    return { request, escrow, index, terms, sig };
+  }
+
+  const cancel = (user: anchor.web3.Keypair, request: anchor.web3.PublicKey) =>
+    program.methods
+      .cancelRequest()
+      .accountsPartial({
+        state: statePda,
+        request,
+        userState: userStatePda(user.publicKey),
+        escrow: escrowPda(request),
+        user: user.publicKey,
+        refundTo: user.publicKey,
+      })
+      .signers([user])
+      .rpc({ commitment: "confirmed" });
+
+  function makeReceipt(objectIds = [bytes(32, 3), bytes(32, 4)]) {
+    const blobId = randomHash();
+    const suiTxHash = randomHash();
+    const proofHash = [...keccak_256(Buffer.from([...blobId, ...suiTxHash]))];
+    return {
+      blobId,
+      suiTxHash,
+      proofHash,
+      suiCheckpoint: new BN(42),
+      deadline: new BN(Math.floor(Date.now() / 1000) + 600),
+      suiObjectIds: objectIds,
+    };
+  }
+  type Receipt = ReturnType<typeof makeReceipt>;
+
+  // Mirrors `build_receipt_message` in lib.rs.
+  function receiptMessage(
+    request: anchor.web3.PublicKey,
+    receipt: Receipt,
+    nonce: number,
+    domain = RECEIPT_DOMAIN
+  ) {
+    return Buffer.from(
+      keccak_256(
+        Buffer.concat([
+          domain,
+          program.programId.toBuffer(),
+          request.toBuffer(),
+          Buffer.from(receipt.blobId),
+          Buffer.from(receipt.suiTxHash),
+          Buffer.from(receipt.proofHash),
+          receipt.suiCheckpoint.toArrayLike(Buffer, "le", 8),
+          new BN(nonce).toArrayLike(Buffer, "le", 8),
+          receipt.deadline.toArrayLike(Buffer, "le", 8),
+          Buffer.from(receipt.suiObjectIds[0]),
+          Buffer.from(receipt.suiObjectIds[1]),
+        ])
+      )
+    );
+  }
+
+  const signIx = (signer: anchor.web3.Keypair, message: Buffer) =>
+    Ed25519Program.createInstructionWithPrivateKey({
+      privateKey: signer.secretKey,
+      message,
+    });
+
+  function verifyBuilder(
+    request: anchor.web3.PublicKey,
+    user: anchor.web3.PublicKey,
+    receipt: Receipt,
+    signedMessage: Buffer,
+    signers = [relayer]
+  ) {
+    return program.methods
+      .verifyReceipt(
+        receipt.blobId,
+        receipt.suiTxHash,
+        receipt.proofHash,
+        receipt.suiCheckpoint,
+        receipt.deadline,
+        receipt.suiObjectIds
+      )
+      .accountsPartial({
+        state: statePda,
+        request,
+        userState: userStatePda(user),
+        escrow: escrowPda(request),
+        vault: vaultPda,
+        relayer: relayer.publicKey,
+        instructionSysvar: SYSVAR_INSTRUCTIONS_PUBKEY,
+      })
+      .preInstructions(signers.map((s) => signIx(s, signedMessage)));
+  }
+
+  async function confirm(
+    request: anchor.web3.PublicKey,
+    user: anchor.web3.PublicKey,
+    objectIds?: number[][]
+  ) {
+    const req = await program.account.request.fetch(request);
+    const receipt = makeReceipt(objectIds);
+    const message = receiptMessage(request, receipt, req.nonce.toNumber());
+    await verifyBuilder(request, user, receipt, message).rpc({
+      commitment: "confirmed",
+    });
+    return receipt;
+  }
+
+  async function openConfirmed(user: anchor.web3.Keypair) {
+    const opened = await openRequest(user);
+    await confirm(opened.request, user.publicKey);
+    return opened;
+  }
+
+  const canAccess = (
+    request: anchor.web3.PublicKey,
+    requester: anchor.web3.PublicKey
+  ) =>
+    program.methods
+      .checkAccess(requester)
+      .accountsPartial({ request })
+      .view() as Promise<boolean>;
+
+  const transfer = (
+    owner: anchor.web3.Keypair,
+    request: anchor.web3.PublicKey,
+    newOwner: anchor.web3.PublicKey
+  ) =>
+    program.methods
+      .transferRequest(newOwner)
+      .accountsPartial({ request, user: owner.publicKey })
+      .signers([owner])
+      .rpc({ commitment: "confirmed" });
+
+  const markFailed = (
+    user: anchor.web3.PublicKey,
+    request: anchor.web3.PublicKey
+  ) =>
+    program.methods
+      .markFailed()
+      .accountsPartial({
+        state: statePda,
+        request,
+        userState: userStatePda(user),
+        refundTo: user,
+        escrow: escrowPda(request),
+        vault: vaultPda,
+        relayer: relayer.publicKey,
+      })
+      .rpc({ commitment: "confirmed" });
+
+  const writable = (...keys: anchor.web3.PublicKey[]) =>
+    keys.map((pubkey) => ({ pubkey, isSigner: false, isWritable: true }));
+
+  async function verifyBatch(
+    items: { request: anchor.web3.PublicKey; user: anchor.web3.PublicKey }[],
+    signers = [relayer]
+  ) {
+    const receipts: Receipt[] = [];
+    const signatures: anchor.web3.TransactionInstruction[] = [];
+    for (const { request } of items) {
+      const req = await program.account.request.fetch(request);
+      const receipt = makeReceipt();
+      const message = receiptMessage(request, receipt, req.nonce.toNumber());
+      receipts.push(receipt);
+      signatures.push(...signers.map((s) => signIx(s, message)));
+    }
+    return program.methods
+      .verifyReceiptBatch(receipts)
+      .accountsPartial({
+        state: statePda,
+        vault: vaultPda,
+        relayer: relayer.publicKey,
+        instructionSysvar: SYSVAR_INSTRUCTIONS_PUBKEY,
+      })
+      .remainingAccounts(
+        items.flatMap(({ request, user }) =>
+          writable(request, escrowPda(request), userStatePda(user))
+        )
+      )
+      .preInstructions(signatures)
+      .rpc({ commitment: "confirmed" });
+  }
+
+  const unpause = () =>
+    program.methods
+      .unpause()
+      .accountsPartial({ state: statePda, admin: admin.publicKey })
+      .rpc({ commitment: "confirmed" });
+  const pauseAs = (authority: anchor.web3.Keypair) =>
+    program.methods
+      .pause()
+      .accountsPartial({ state: statePda, authority: authority.publicKey })
+      .signers([authority])
+      .rpc({ commitment: "confirmed" });
 
   it("Is initialized!", async () => {
     // Add your test here.
-    const tx = await program.methods.initialize().rpc();
+    const tx = await program.methods
+      .initialize(anchor.getProvider().publicKey, 0)
+      .rpc();
     console.log("Your transaction signature", tx);
   });
+
+  describe("admin transfer", () => {
+    const propose = (
+      newAdmin: anchor.web3.PublicKey,
+      signer: anchor.web3.Keypair = admin
+    ) =>
+      program.methods
+        .proposeAdmin(newAdmin)
+        .accountsPartial({ state: statePda, admin: signer.publicKey })
+        .signers([signer])
+        .rpc();
+    const accept = (signer: anchor.web3.Keypair) =>
+      program.methods
+        .acceptAdmin()
+        .accountsPartial({ state: statePda, newAdmin: signer.publicKey })
+        .signers([signer])
+        .rpc({ commitment: "confirmed" });
+
+    it("only lets the proposed admin accept", async () => {
+      const candidate = await funded(1);
+      const stranger = await funded(1);
+      await propose(candidate.publicKey);
+      let state = await program.account.state.fetch(statePda);
+      expect(state.pendingAdmin.toBase58()).to.equal(
+        candidate.publicKey.toBase58()
+      );
+      expect(state.admin.toBase58()).to.equal(admin.publicKey.toBase58());
+
+      await expectErr(accept(stranger), "Unauthorized");
+      await expectErr(accept(admin), "Unauthorized");
+
+      const sig = await accept(candidate);
+      const [transferred] = named(await eventsOf(sig), "AdminTransferred");
+      expect(transferred.data.old.toBase58()).to.equal(
+        admin.publicKey.toBase58()
+      );
+      expect(transferred.data.new.toBase58()).to.equal(
+        candidate.publicKey.toBase58()
+      );
+      state = await program.account.state.fetch(statePda);
+      expect(state.admin.toBase58()).to.equal(candidate.publicKey.toBase58());
+      expect(state.pendingAdmin.toBase58()).to.equal(
+        PublicKey.default.toBase58()
+      );
+
+      // The old admin is locked out, and the new one can hand it back.
+      await expectErr(propose(admin.publicKey), "ConstraintHasOne");
+      await propose(admin.publicKey, candidate);
+      await accept(admin);
+      state = await program.account.state.fetch(statePda);
+      expect(state.admin.toBase58()).to.equal(admin.publicKey.toBase58());
+    });
+
+    it("lets the admin withdraw a proposal", async () => {
+      const candidate = await funded(1);
+      await propose(candidate.publicKey);
+      await program.methods
+        .clearPendingAdmin()
+        .accountsPartial({ state: statePda, admin: admin.publicKey })
+        .rpc();
+      const state = await program.account.state.fetch(statePda);
+      expect(state.pendingAdmin.toBase58()).to.equal(
+        PublicKey.default.toBase58()
+      );
+      await expectErr(accept(candidate), "Unauthorized");
+    });
+
+    it("rejects the default key as a proposal", async () => {
+      await expectErr(propose(PublicKey.default), "InvalidAdmin");
+    });
+  });
+
+  describe("relayer rotation", () => {
+    // The incoming relayer co-signs to post its bond.
+    const rotate = (
+      newRelayer: anchor.web3.PublicKey,
+      signer: anchor.web3.Keypair = admin
+    ) =>
+      program.methods
+        .setRelayer(newRelayer)
+        .accountsPartial({
+          state: statePda,
+          admin: admin.publicKey,
+          relayerSigner: signer.publicKey,
+          bond: bondPda(newRelayer),
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([signer])
+        .rpc({ commitment: "confirmed" });
+
+    it("swaps the primary relayer in place", async () => {
+      const next = await funded(2);
+      const before = await program.account.state.fetch(statePda);
+      const sig = await rotate(next.publicKey, next);
+      const [updated] = named(await eventsOf(sig), "RelayerUpdated");
+      expect(updated.data.oldRelayer.toBase58()).to.equal(
+        admin.publicKey.toBase58()
+      );
+      expect(updated.data.newRelayer.toBase58()).to.equal(
+        next.publicKey.toBase58()
+      );
+
+      const state = await program.account.state.fetch(statePda);
+      expect(state.relayer.toBase58()).to.equal(next.publicKey.toBase58());
+      expect(state.relayerCount).to.equal(before.relayerCount);
+      expect(await isRelayer(next.publicKey)).to.be.true;
+      expect(await isRelayer(admin.publicKey)).to.be.false;
+      const bond = await program.account.relayerBond.fetch(
+        bondPda(next.publicKey)
+      );
+      expect(bond.amount.toNumber()).to.equal(before.relayerBond.toNumber());
+
+      await rotate(admin.publicKey);
+      expect(await isRelayer(admin.publicKey)).to.be.true;
+      expect(await isRelayer(next.publicKey)).to.be.false;
+    });
+
+    it("rejects an enrolled or default key", async () => {
+      await expectErr(rotate(admin.publicKey), "DuplicateRelayer");
+      await expectErr(rotate(PublicKey.default), "InvalidRelayer");
+    });
+
+    it("needs the new relayer's signature", async () => {
+      const next = await funded(2);
+      await expectErr(rotate(next.publicKey, admin), "InvalidRelayer");
+    });
+  });
+
+  describe("relayer allowlist", () => {
+    it("adds and removes relayers", async () => {
+      const before = await program.account.state.fetch(statePda);
+      const extra = await enrollRelayer();
+      expect(await isRelayer(extra.publicKey)).to.be.true;
+      let state = await program.account.state.fetch(statePda);
+      expect(state.relayerCount).to.equal(before.relayerCount + 1);
+
+      const sig = await removeRelayer(extra.publicKey);
+      const [removed] = named(await eventsOf(sig), "RelayerRemoved");
+      expect(removed.data.relayer.toBase58()).to.equal(
+        extra.publicKey.toBase58()
+      );
+      expect(await isRelayer(extra.publicKey)).to.be.false;
+      state = await program.account.state.fetch(statePda);
+      expect(state.relayerCount).to.equal(before.relayerCount);
+    });
+
+    it("keeps the primary relayer and rejects unknown keys", async () => {
+      await expectErr(removeRelayer(admin.publicKey), "InvalidRelayer");
+      await expectErr(
+        removeRelayer(Keypair.generate().publicKey),
+        "RelayerNotFound"
+      );
+    });
+  });
+
+  describe("escrow", () => {
+    it("holds each pending payment in the request's own escrow", async () => {
+      const user = await funded();
+      const vaultBefore = await connection.getBalance(vaultPda);
+      const { request, escrow } = await openRequest(user);
+
+      const req = await program.account.request.fetch(request);
+      expect(req.status).to.deep.equal({ pending: {} });
+      const rent = await connection.getMinimumBalanceForRentExemption(8);
+      expect(await connection.getBalance(escrow)).to.equal(
+        rent + req.payment.toNumber()
+      );
+      expect(await connection.getBalance(vaultPda)).to.equal(vaultBefore);
+      const state = await program.account.state.fetch(statePda);
+      expect(state.lockedLamports.toNumber()).to.be.at.least(
+        req.payment.toNumber()
+      );
+    });
+  });
+
+  describe("cancellation", () => {
+    it("refunds a cancelled request in full", async () => {
+      const user = await funded();
+      const { request, escrow } = await openRequest(user);
+      const req = await program.account.request.fetch(request);
+      const state = await program.account.state.fetch(statePda);
+      const sig = await cancel(user, request);
+
+      const [cancelled] = named(await eventsOf(sig), "RequestCancelled");
+      expect(cancelled.data.refund.toNumber()).to.equal(req.payment.toNumber());
+      const rent = await connection.getMinimumBalanceForRentExemption(8);
+      expect(await connection.getBalance(escrow)).to.equal(rent);
+      const after = await program.account.state.fetch(statePda);
+      expect(after.lockedLamports.toNumber()).to.equal(
+        state.lockedLamports.toNumber() - req.payment.toNumber()
+      );
+      const cancelledReq = await program.account.request.fetch(request);
+      expect(cancelledReq.status).to.deep.equal({ cancelled: {} });
+      await expectErr(cancel(user, request), "InvalidStatus");
+    });
+
+    it("only lets the owner cancel", async () => {
+      const user = await funded();
+      const stranger = await funded(1);
+      const { request } = await openRequest(user);
+      await expectErr(
+        program.methods
+          .cancelRequest()
+          .accountsPartial({
+            state: statePda,
+            request,
+            userState: userStatePda(user.publicKey),
+            escrow: escrowPda(request),
+            user: stranger.publicKey,
+            refundTo: user.publicKey,
+          })
+          .signers([stranger])
+          .rpc(),
+        "Unauthorized"
+      );
+    });
+  });
+
+  describe("timeouts", () => {
+    const setTimeoutSecs = (secs: anchor.BN) =>
+      program.methods
+        .setTimeout(secs)
+        .accountsPartial({ state: statePda, admin: admin.publicKey })
+        .rpc();
+    const timeout = (
+      user: anchor.web3.Keypair,
+      request: anchor.web3.PublicKey
+    ) =>
+      program.methods
+        .timeoutRequest()
+        .accountsPartial({
+          state: statePda,
+          request,
+          userState: userStatePda(user.publicKey),
+          escrow: escrowPda(request),
+          refundTo: user.publicKey,
+        })
+        .rpc({ commitment: "confirmed" });
+
+    it("lets anyone refund a stalled request once the timeout passes", async () => {
+      const user = await funded();
+      const { request } = await openRequest(user);
+      await expectErr(timeout(user, request), "TimeoutNotReached");
+
+      const { timeoutSecs } = await program.account.state.fetch(statePda);
+      await setTimeoutSecs(new BN(1));
+      try {
+        await sleep(2_500);
+        const req = await program.account.request.fetch(request);
+        const before = await connection.getBalance(user.publicKey);
+        const sig = await timeout(user, request);
+        expect(named(await eventsOf(sig), "RequestTimedOut")).to.have.length(1);
+        expect(await connection.getBalance(user.publicKey)).to.equal(
+          before + req.payment.toNumber()
+        );
+        const failed = await program.account.request.fetch(request);
+        expect(failed.status).to.deep.equal({ failed: {} });
+      } finally {
+        await setTimeoutSecs(timeoutSecs);
+      }
+    });
+  });
+
+  describe("token payments", () => {
+    let mint: anchor.web3.PublicKey;
+    let vaultToken: anchor.web3.PublicKey;
+    let user: anchor.web3.Keypair;
+    let userToken: anchor.web3.PublicKey;
+
+    const tokenBalance = async (account: anchor.web3.PublicKey) =>
+      Number((await getAccount(connection, account)).amount);
+    const openTokenRequest = async (
+      payWith: anchor.web3.PublicKey = mint,
+      from: anchor.web3.PublicKey = userToken
+    ) => {
+      const index = await nextIndex(user.publicKey);
+      const request = requestPda(user.publicKey, index);
+      const terms = requestTerms();
+      await program.methods
+        .initiateStorageSpl(
+          terms.dataHash,
+          terms.paymentAmount,
+          terms.dataSize,
+          terms.durationSecs,
+          terms.contentType,
+          terms.encKeyCommitment,
+          terms.dedup,
+          terms.priority,
+          terms.maxAcceptablePayment,
+          terms.memo,
+          terms.approvedRelayer,
+          terms.tier,
+          terms.tags
+        )
+        .accountsPartial({
+          state: statePda,
+          userState: userStatePda(user.publicKey),
+          request,
+          escrow: escrowPda(request),
+          hashRegistry: hashPda(terms.dataHash),
+          mint: payWith,
+          userToken: from,
+          vault: vaultPda,
+          vaultToken,
+          user: user.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc({ commitment: "confirmed" });
+      return request;
+    };
+    const refundAccounts = (request: anchor.web3.PublicKey) => ({
+      state: statePda,
+      request,
+      userState: userStatePda(user.publicKey),
+      userToken,
+      vault: vaultPda,
+      vaultToken,
+      tokenProgram: TOKEN_PROGRAM_ID,
+    });
+
+    before(async () => {
+      user = await funded();
+      mint = await createMint(connection, admin, admin.publicKey, null, 6);
+      vaultToken = (
+        await getOrCreateAssociatedTokenAccount(
+          connection,
+          admin,
+          mint,
+          vaultPda,
+          true
+        )
+      ).address;
+      userToken = (
+        await getOrCreateAssociatedTokenAccount(
+          connection,
+          admin,
+          mint,
+          user.publicKey
+        )
+      ).address;
+      await mintTo(connection, admin, mint, userToken, admin, 100_000_000);
+      await program.methods
+        .setPaymentMint()
+        .accountsPartial({ state: statePda, mint, admin: admin.publicKey })
+        .rpc();
+    });
+
+    it("escrows tokens in the vault and refunds them on failure", async () => {
+      const before = await program.account.state.fetch(statePda);
+      const request = await openTokenRequest();
+      const req = await program.account.request.fetch(request);
+      expect(req.paymentMint.toBase58()).to.equal(mint.toBase58());
+      expect(await tokenBalance(vaultToken)).to.equal(req.payment.toNumber());
+      let state = await program.account.state.fetch(statePda);
+      expect(state.lockedTokens.toNumber()).to.equal(
+        before.lockedTokens.toNumber() + req.payment.toNumber()
+      );
+
+      const userBefore = await tokenBalance(userToken);
+      await program.methods
+        .markFailedSpl()
+        .accountsPartial({
+          ...refundAccounts(request),
+          relayer: admin.publicKey,
+        })
+        .rpc({ commitment: "confirmed" });
+      expect(await tokenBalance(userToken)).to.equal(
+        userBefore + req.payment.toNumber()
+      );
+      state = await program.account.state.fetch(statePda);
+      expect(state.lockedTokens.toNumber()).to.equal(
+        before.lockedTokens.toNumber()
+      );
+    });
+
+    it("refunds tokens when the owner cancels", async () => {
+      const request = await openTokenRequest();
+      const req = await program.account.request.fetch(request);
+      const userBefore = await tokenBalance(userToken);
+      await program.methods
+        .cancelRequestSpl()
+        .accountsPartial({ ...refundAccounts(request), user: user.publicKey })
+        .signers([user])
+        .rpc({ commitment: "confirmed" });
+      expect(await tokenBalance(userToken)).to.equal(
+        userBefore + req.payment.toNumber()
+      );
+    });
+
+    it("rejects payment in any other mint", async () => {
+      const otherMint = await createMint(
+        connection,
+        admin,
+        admin.publicKey,
+        null,
+        6
+      );
+      const otherToken = (
+        await getOrCreateAssociatedTokenAccount(
+          connection,
+          admin,
+          otherMint,
+          user.publicKey
+        )
+      ).address;
+      await mintTo(connection, admin, otherMint, otherToken, admin, 10_000_000);
+      await expectErr(openTokenRequest(otherMint, otherToken), "InvalidMint");
+    });
+  });
+
+  describe("delegates", () => {
+    let user: anchor.web3.Keypair;
+    const grant = (
+      request: anchor.web3.PublicKey,
+      delegate: anchor.web3.PublicKey
+    ) =>
+      program.methods
+        .grantAccess(delegate)
+        .accountsPartial({ request, user: user.publicKey })
+        .signers([user])
+        .rpc({ commitment: "confirmed" });
+    const revoke = (
+      request: anchor.web3.PublicKey,
+      delegate: anchor.web3.PublicKey
+    ) =>
+      program.methods
+        .revokeDelegate(delegate)
+        .accountsPartial({ request, user: user.publicKey })
+        .signers([user])
+        .rpc({ commitment: "confirmed" });
+
+    before(async () => {
+      user = await funded();
+    });
+
+    it("grants and revokes read access", async () => {
+      const { request } = await openConfirmed(user);
+      const reader = Keypair.generate().publicKey;
+      expect(await canAccess(request, user.publicKey)).to.be.true;
+      expect(await canAccess(request, reader)).to.be.false;
+
+      const sig = await grant(request, reader);
+      const [granted] = named(await eventsOf(sig), "AccessGranted");
+      expect(granted.data.delegate.toBase58()).to.equal(reader.toBase58());
+      expect(await canAccess(request, reader)).to.be.true;
+      await expectErr(grant(request, reader), "DuplicateDelegate");
+
+      await revoke(request, reader);
+      expect(await canAccess(request, reader)).to.be.false;
+      await expectErr(revoke(request, reader), "DelegateNotFound");
+    });
+
+    it("only lets the owner manage delegates", async () => {
+      const { request } = await openConfirmed(user);
+      const stranger = await funded(1);
+      await expectErr(
+        program.methods
+          .grantAccess(stranger.publicKey)
+          .accountsPartial({ request, user: stranger.publicKey })
+          .signers([stranger])
+          .rpc(),
+        "Unauthorized"
+      );
+    });
+
+    it("renews a lapsed grant in place", async () => {
+      const { request } = await openConfirmed(user);
+      const reader = Keypair.generate().publicKey;
+      const grantUntil = (expiresAt: number) =>
+        program.methods
+          .grantAccessUntil(reader, new BN(expiresAt))
+          .accountsPartial({ request, user: user.publicKey })
+          .signers([user])
+          .rpc({ commitment: "confirmed" });
+      const now = Math.floor(Date.now() / 1000);
+      await grantUntil(now + 2);
+      expect(await canAccess(request, reader)).to.be.true;
+      await expectErr(grantUntil(now + 600), "DuplicateDelegate");
+
+      await sleep(3_500);
+      expect(await canAccess(request, reader)).to.be.false;
+      await grantUntil(now + 600);
+      expect(await canAccess(request, reader)).to.be.true;
+      const req = await program.account.request.fetch(request);
+      expect(req.delegateCount).to.equal(1);
+    });
+  });
+
+  describe("ownership transfer", () => {
+    it("hands a confirmed request and its access to the new owner", async () => {
+      const user = await funded();
+      const { request } = await openConfirmed(user);
+      const reader = Keypair.generate().publicKey;
+      await program.methods
+        .grantAccess(reader)
+        .accountsPartial({ request, user: user.publicKey })
+        .signers([user])
+        .rpc();
+      const newOwner = await funded(1);
+
+      const sig = await transfer(user, request, newOwner.publicKey);
+      const [moved] = named(await eventsOf(sig), "OwnershipTransferred");
+      expect(moved.data.old.toBase58()).to.equal(user.publicKey.toBase58());
+      expect(moved.data.new.toBase58()).to.equal(newOwner.publicKey.toBase58());
+
+      const req = await program.account.request.fetch(request);
+      expect(req.user.toBase58()).to.equal(newOwner.publicKey.toBase58());
+      expect(req.delegateCount).to.equal(0);
+      expect(await canAccess(request, newOwner.publicKey)).to.be.true;
+      expect(await canAccess(request, user.publicKey)).to.be.false;
+      expect(await canAccess(request, reader)).to.be.false;
+      await expectErr(transfer(user, request, user.publicKey), "Unauthorized");
+    });
+
+    it("only moves confirmed requests, to a real key", async () => {
+      const user = await funded();
+      const { request } = await openRequest(user);
+      const newOwner = Keypair.generate().publicKey;
+      await expectErr(transfer(user, request, newOwner), "InvalidStatus");
+      await confirm(request, user.publicKey);
+      await expectErr(
+        transfer(user, request, PublicKey.default),
+        "InvalidOwner"
+      );
+    });
+  });
+
+  describe("batch failure", () => {
+    it("fails every pending request in the batch and skips the rest", async () => {
+      const user = await funded();
+      const first = await openRequest(user);
+      const second = await openRequest(user);
+      const done = await openConfirmed(user);
+      const slots = [first, second, done].flatMap(({ request, escrow }) =>
+        writable(request, escrow, user.publicKey, userStatePda(user.publicKey))
+      );
+      const userState = await program.account.userState.fetch(
+        userStatePda(user.publicKey)
+      );
+
+      await program.methods
+        .markFailedBatch(3)
+        .accountsPartial({
+          state: statePda,
+          vault: vaultPda,
+          relayer: relayer.publicKey,
+        })
+        .remainingAccounts(slots)
+        .rpc({ commitment: "confirmed" });
+
+      for (const { request } of [first, second]) {
+        const req = await program.account.request.fetch(request);
+        expect(req.status).to.deep.equal({ failed: {} });
+      }
+      const confirmed = await program.account.request.fetch(done.request);
+      expect(confirmed.status).to.deep.equal({ confirmed: {} });
+      const after = await program.account.userState.fetch(
+        userStatePda(user.publicKey)
+      );
+      expect(after.pendingCount).to.equal(userState.pendingCount - 2);
+    });
+
+    it("rejects a batch whose size doesn't match its accounts", async () => {
+      const user = await funded();
+      const { request, escrow } = await openRequest(user);
+      await expectErr(
+        program.methods
+          .markFailedBatch(2)
+          .accountsPartial({
+            state: statePda,
+            vault: vaultPda,
+            relayer: relayer.publicKey,
+          })
+          .remainingAccounts(
+            writable(
+              request,
+              escrow,
+              user.publicKey,
+              userStatePda(user.publicKey)
+            )
+          )
+          .rpc(),
+        "InvalidBatch"
+      );
+    });
+  });
+
+  describe("batch confirmation", () => {
+    it("confirms several requests in one transaction", async () => {
+      const user = await funded();
+      const items = [];
+      for (let i = 0; i < 2; i++) {
+        const { request } = await openRequest(user);
+        items.push({ request, user: user.publicKey });
+      }
+      const sig = await verifyBatch(items);
+      expect(named(await eventsOf(sig), "StorageConfirmed")).to.have.length(2);
+      for (const { request } of items) {
+        const req = await program.account.request.fetch(request);
+        expect(req.status).to.deep.equal({ confirmed: {} });
+      }
+    });
+
+    it("rejects a receipt for an already confirmed request", async () => {
+      const user = await funded();
+      const { request } = await openConfirmed(user);
+      await expectErr(
+        verifyBatch([{ request, user: user.publicKey }]),
+        "InvalidStatus"
+      );
+    });
+  });
+
+  describe("secp256k1 receipts", () => {
+    const ethKey = secp256k1.utils.randomPrivateKey();
+    const ethAddress = [
+      ...Secp256k1Program.publicKeyToEthAddress(
+        secp256k1.getPublicKey(ethKey, false).slice(1)
+      ),
+    ];
+    const setEthAddress = (address: number[]) =>
+      program.methods
+        .setRelayerEthAddress(address)
+        .accountsPartial({ state: statePda, admin: admin.publicKey })
+        .rpc();
+    const verifySecp = async (
+      user: anchor.web3.Keypair,
+      request: anchor.web3.PublicKey,
+      privateKey: Uint8Array
+    ) => {
+      const req = await program.account.request.fetch(request);
+      const receipt = makeReceipt();
+      const message = receiptMessage(request, receipt, req.nonce.toNumber());
+      return program.methods
+        .verifyReceiptSecp256k1(
+          receipt.blobId,
+          receipt.suiTxHash,
+          receipt.proofHash,
+          receipt.suiCheckpoint,
+          receipt.deadline,
+          receipt.suiObjectIds
+        )
+        .accountsPartial({
+          state: statePda,
+          request,
+          userState: userStatePda(user.publicKey),
+          escrow: escrowPda(request),
+          vault: vaultPda,
+          relayer: relayer.publicKey,
+          instructionSysvar: SYSVAR_INSTRUCTIONS_PUBKEY,
+        })
+        .preInstructions([
+          Secp256k1Program.createInstructionWithPrivateKey({
+            privateKey,
+            message,
+          }),
+        ])
+        .rpc({ commitment: "confirmed" });
+    };
+
+    before(async () => {
+      await setEthAddress(ethAddress);
+    });
+    after(async () => {
+      await setEthAddress(bytes(20, 0));
+    });
+
+    it("confirms with the enrolled Ethereum key", async () => {
+      const user = await funded();
+      const { request } = await openRequest(user);
+      await verifySecp(user, request, ethKey);
+      const req = await program.account.request.fetch(request);
+      expect(req.status).to.deep.equal({ confirmed: {} });
+      expect(req.confirmedBy.toBase58()).to.equal(
+        relayer.publicKey.toBase58()
+      );
+    });
+
+    it("rejects any other Ethereum key", async () => {
+      const user = await funded();
+      const { request } = await openRequest(user);
+      await expectErr(
+        verifySecp(user, request, secp256k1.utils.randomPrivateKey()),
+        "InvalidSignature"
+      );
+    });
+  });
+
+  describe("relayer quorum", () => {
+    const setThreshold = (threshold: number) =>
+      program.methods
+        .setThreshold(threshold)
+        .accountsPartial({ state: statePda, admin: admin.publicKey })
+        .rpc();
+    let second: anchor.web3.Keypair;
+
+    before(async () => {
+      second = await enrollRelayer();
+      await setThreshold(2);
+    });
+    after(async () => {
+      await setThreshold(1);
+      await removeRelayer(second.publicKey);
+    });
+
+    it("needs every required signature on a single receipt", async () => {
+      const user = await funded();
+      const { request } = await openRequest(user);
+      const req = await program.account.request.fetch(request);
+      const receipt = makeReceipt();
+      const message = receiptMessage(request, receipt, req.nonce.toNumber());
+      await expectErr(
+        verifyBuilder(request, user.publicKey, receipt, message).rpc(),
+        "ThresholdNotMet"
+      );
+      await verifyBuilder(request, user.publicKey, receipt, message, [
+        relayer,
+        second,
+      ]).rpc({ commitment: "confirmed" });
+      const confirmed = await program.account.request.fetch(request);
+      expect(confirmed.status).to.deep.equal({ confirmed: {} });
+    });
+
+    it("confirms batches at a threshold above one", async () => {
+      const user = await funded();
+      const { request } = await openRequest(user);
+      const items = [{ request, user: user.publicKey }];
+      await expectErr(verifyBatch(items), "ThresholdNotMet");
+      await verifyBatch(items, [relayer, second]);
+      const confirmed = await program.account.request.fetch(request);
+      expect(confirmed.status).to.deep.equal({ confirmed: {} });
+    });
+
+    it("won't raise the threshold past the relayer count", async () => {
+      await expectErr(setThreshold(3), "InvalidThreshold");
+    });
+  });
+
+  describe("guardian", () => {
+    it("can pause but not unpause", async () => {
+      const guardian = await funded(1);
+      const sig = await program.methods
+        .setGuardian(guardian.publicKey)
+        .accountsPartial({ state: statePda, admin: admin.publicKey })
+        .rpc({ commitment: "confirmed" });
+      const [updated] = named(await eventsOf(sig), "GuardianUpdated");
+      expect(updated.data.newGuardian.toBase58()).to.equal(
+        guardian.publicKey.toBase58()
+      );
+
+      try {
+        await pauseAs(guardian);
+        const paused = await program.account.state.fetch(statePda);
+        expect(paused.pauseFlags).to.not.equal(0);
+        await expectErr(
+          program.methods
+            .unpause()
+            .accountsPartial({ state: statePda, admin: guardian.publicKey })
+            .signers([guardian])
+            .rpc(),
+          "ConstraintHasOne"
+        );
+      } finally {
+        await unpause();
+      }
+      const state = await program.account.state.fetch(statePda);
+      expect(state.pauseFlags).to.equal(0);
+    });
+
+    it("won't let anyone else pause", async () => {
+      await expectErr(pauseAs(await funded(1)), "Unauthorized");
+    });
+  });
+
+  describe("withdraw timelock", () => {
+    const adminOnly = { state: statePda, admin: admin.publicKey };
+    const setDelay = (secs: number) =>
+      program.methods
+        .setWithdrawDelay(new BN(secs))
+        .accountsPartial(adminOnly)
+        .rpc({ commitment: "confirmed" });
+    const applyDelay = () =>
+      program.methods
+        .applyWithdrawDelay()
+        .accountsPartial(adminOnly)
+        .rpc({ commitment: "confirmed" });
+    const setRecipient = (recipient: anchor.web3.PublicKey) =>
+      program.methods
+        .setFeeRecipient(recipient)
+        .accountsPartial(adminOnly)
+        .rpc({ commitment: "confirmed" });
+    const applyRecipient = () =>
+      program.methods
+        .applyFeeRecipient()
+        .accountsPartial(adminOnly)
+        .rpc({ commitment: "confirmed" });
+    const withdraw = (amount: number) =>
+      program.methods
+        .withdraw(new BN(amount))
+        .accountsPartial({
+          ...adminOnly,
+          vault: vaultPda,
+          recipient: admin.publicKey,
+        })
+        .rpc({ commitment: "confirmed" });
+    // Lowering the delay waits out the one in force.
+    async function resetDelay() {
+      await setDelay(0);
+      await sleep(2_500);
+      await applyDelay();
+    }
+
+    it("only pays a queued withdrawal after the delay", async () => {
+      const user = await funded();
+      const { request } = await openRequest(user);
+      await confirm(request, user.publicKey);
+      await setDelay(2);
+      try {
+        await program.methods
+          .queueWithdraw(new BN(1_000))
+          .accountsPartial(adminOnly)
+          .rpc();
+        await expectErr(withdraw(1_000), "WithdrawLocked");
+        await expectErr(withdraw(999), "WithdrawNotQueued");
+        await sleep(2_500);
+        const sig = await withdraw(1_000);
+        const [withdrawn] = named(await eventsOf(sig), "Withdrawn");
+        expect(withdrawn.data.amount.toNumber()).to.equal(1_000);
+      } finally {
+        await resetDelay();
+      }
+    });
+
+    it("raises the delay at once but queues a lower one", async () => {
+      const sig = await setDelay(2);
+      expect(named(await eventsOf(sig), "WithdrawDelayUpdated")).to.have.length(
+        1
+      );
+      const events = await eventsOf(await setDelay(0));
+      expect(named(events, "WithdrawDelayQueued")).to.have.length(1);
+      const state = await program.account.state.fetch(statePda);
+      expect(state.withdrawDelaySecs.toNumber()).to.equal(2);
+      await expectErr(applyDelay(), "ChangeLocked");
+
+      await sleep(2_500);
+      const applied = await applyDelay();
+      const [updated] = named(await eventsOf(applied), "WithdrawDelayUpdated");
+      expect(updated.data.delaySecs.toNumber()).to.equal(0);
+      await expectErr(applyDelay(), "ChangeNotQueued");
+    });
+
+    it("queues a fee recipient change behind the delay", async () => {
+      const treasury = Keypair.generate().publicKey;
+      await setDelay(2);
+      try {
+        const sig = await setRecipient(treasury);
+        expect(named(await eventsOf(sig), "FeeRecipientQueued")).to.have.length(
+          1
+        );
+        const state = await program.account.state.fetch(statePda);
+        expect(state.feeRecipient.toBase58()).to.not.equal(treasury.toBase58());
+        await expectErr(applyRecipient(), "ChangeLocked");
+
+        await sleep(2_500);
+        const applied = await applyRecipient();
+        const [updated] = named(await eventsOf(applied), "FeeRecipientUpdated");
+        expect(updated.data.newRecipient.toBase58()).to.equal(
+          treasury.toBase58()
+        );
+      } finally {
+        await resetDelay();
+        // With no delay the change applies at once.
+        await setRecipient(PublicKey.default);
+      }
+      const state = await program.account.state.fetch(statePda);
+      expect(state.feeRecipient.toBase58()).to.equal(
+        PublicKey.default.toBase58()
+      );
+    });
+  });
+
+  describe("retries", () => {
+    const retry = (
+      user: anchor.web3.Keypair,
+      request: anchor.web3.PublicKey,
+      payment = 2_000_000
+    ) =>
+      program.methods
+        .retryRequest(new BN(payment))
+        .accountsPartial({
+          state: statePda,
+          request,
+          userState: userStatePda(user.publicKey),
+          escrow: escrowPda(request),
+          user: user.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc({ commitment: "confirmed" });
+
+    it("reopens a failed request with a fresh payment", async () => {
+      const user = await funded();
+      const { request, escrow } = await openRequest(user);
+      await expectErr(retry(user, request), "InvalidStatus");
+      await markFailed(user.publicKey, request);
+
+      const escrowBefore = await connection.getBalance(escrow);
+      const sig = await retry(user, request, 3_000_000);
+      const [retried] = named(await eventsOf(sig), "RequestRetried");
+      expect(retried.data.payment.toNumber()).to.equal(3_000_000);
+      const req = await program.account.request.fetch(request);
+      expect(req.status).to.deep.equal({ pending: {} });
+      expect(req.payment.toNumber()).to.equal(3_000_000);
+      expect(await connection.getBalance(escrow)).to.equal(
+        escrowBefore + 3_000_000
+      );
+    });
+
+    it("is only open to the request's owner", async () => {
+      const user = await funded();
+      const { request } = await openRequest(user);
+      await markFailed(user.publicKey, request);
+      const other = await funded();
+      await expectErr(
+        program.methods
+          .retryRequest(new BN(2_000_000))
+          .accountsPartial({
+            state: statePda,
+            request,
+            userState: userStatePda(user.publicKey),
+            escrow: escrowPda(request),
+            user: other.publicKey,
+          })
+          .signers([other])
+          .rpc(),
+        "Unauthorized"
+      );
+    });
+  });
+
+  describe("renewals", () => {
+    const renew = (
+      user: anchor.web3.Keypair,
+      request: anchor.web3.PublicKey,
+      additionalSecs = 3 * DAY
+    ) =>
+      program.methods
+        .renewRequest(new BN(additionalSecs), new BN(2_000_000))
+        .accountsPartial({
+          state: statePda,
+          request,
+          vault: vaultPda,
+          user: user.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc({ commitment: "confirmed" });
+
+    it("extends a request close to expiry", async () => {
+      const user = await funded();
+      const { request } = await openRequest(user, {
+        durationSecs: new BN(3 * DAY),
+      });
+      await confirm(request, user.publicKey);
+      const before = await program.account.request.fetch(request);
+
+      const sig = await renew(user, request);
+      const [renewed] = named(await eventsOf(sig), "RequestRenewed");
+      expect(renewed.data.payment.toNumber()).to.equal(2_000_000);
+      const after = await program.account.request.fetch(request);
+      expect(after.expiry.toNumber()).to.equal(
+        before.expiry.toNumber() + 3 * DAY
+      );
+    });
+
+    it("refuses early renewals and renewals while paused", async () => {
+      const user = await funded();
+      const { request } = await openConfirmed(user);
+      await expectErr(renew(user, request), "RenewalTooEarly");
+
+      const near = await openRequest(user, { durationSecs: new BN(3 * DAY) });
+      await confirm(near.request, user.publicKey);
+      await pauseAs(admin);
+      try {
+        await expectErr(renew(user, near.request), "Paused");
+      } finally {
+        await unpause();
+      }
+    });
+  });
+
+  describe("admin force fail", () => {
+    const forceFail = (
+      user: anchor.web3.PublicKey,
+      request: anchor.web3.PublicKey,
+      signer = admin
+    ) =>
+      program.methods
+        .adminForceFail()
+        .accountsPartial({
+          state: statePda,
+          request,
+          userState: userStatePda(user),
+          refundTo: user,
+          escrow: escrowPda(request),
+          vault: vaultPda,
+          admin: signer.publicKey,
+        })
+        .signers([signer])
+        .rpc({ commitment: "confirmed" });
+
+    it("fails a stuck request and refunds the user", async () => {
+      const user = await funded();
+      const { request } = await openRequest(user);
+      const req = await program.account.request.fetch(request);
+      const { failureFeeBps } = await program.account.state.fetch(statePda);
+      const before = await connection.getBalance(user.publicKey);
+
+      await forceFail(user.publicKey, request);
+      const retained = Math.floor(
+        (req.payment.toNumber() * failureFeeBps) / 10_000
+      );
+      expect(await connection.getBalance(user.publicKey)).to.equal(
+        before + req.payment.toNumber() - retained
+      );
+      const failed = await program.account.request.fetch(request);
+      expect(failed.status).to.deep.equal({ failed: {} });
+      await expectErr(forceFail(user.publicKey, request), "InvalidStatus");
+    });
+
+    it("is admin-only", async () => {
+      const user = await funded();
+      const { request } = await openRequest(user);
+      await expectErr(
+        forceFail(user.publicKey, request, user),
+        "ConstraintHasOne"
+      );
+    });
+  });
+
+  describe("failure fee", () => {
+    const setFailureFee = (bps: number) =>
+      program.methods
+        .setFailureFeeBps(bps)
+        .accountsPartial({ state: statePda, admin: admin.publicKey })
+        .rpc({ commitment: "confirmed" });
+
+    it("refunds a failed request less the failure fee", async () => {
+      const user = await funded();
+      const { request } = await openRequest(user);
+      const req = await program.account.request.fetch(request);
+      const { failureFeeBps } = await program.account.state.fetch(statePda);
+      await setFailureFee(1_000);
+      try {
+        const before = await program.account.state.fetch(statePda);
+        const balance = await connection.getBalance(user.publicKey);
+        const sig = await markFailed(user.publicKey, request);
+
+        const retained = Math.floor((req.payment.toNumber() * 1_000) / 10_000);
+        const refunded = req.payment.toNumber() - retained;
+        expect(await connection.getBalance(user.publicKey)).to.equal(
+          balance + refunded
+        );
+        const [failed] = named(await eventsOf(sig), "RequestFailed");
+        expect(failed.data.refunded.toNumber()).to.equal(refunded);
+        expect(failed.data.retained.toNumber()).to.equal(retained);
+        const after = await program.account.state.fetch(statePda);
+        expect(after.accruedFees.toNumber()).to.equal(
+          before.accruedFees.toNumber() + retained
+        );
+      } finally {
+        await setFailureFee(failureFeeBps);
+      }
+    });
+
+    it("rejects a fee above 100%", async () => {
+      await expectErr(setFailureFee(10_001), "InvalidFeeBps");
+    });
+  });
+
+  describe("hash registry", () => {
+    it("releases the entry once its request is no longer confirmed", async () => {
+      const user = await funded();
+      const { request, terms } = await openRequest(user);
+      await confirm(request, user.publicKey);
+      await program.methods
+        .registerHash()
+        .accountsPartial({
+          request,
+          hashRegistry: hashPda(terms.dataHash),
+          payer: admin.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc();
+      await expectErr(
+        openRequest(user, { dataHash: terms.dataHash, dedup: true }),
+        "DuplicateDataHash"
+      );
+
+      const release = () =>
+        program.methods
+          .releaseHash(terms.dataHash)
+          .accountsPartial({
+            hashRegistry: hashPda(terms.dataHash),
+            request,
+            payer: admin.publicKey,
+          })
+          .rpc();
+      await expectErr(release(), "InvalidStatus");
+      await program.methods
+        .revokeAccess()
+        .accountsPartial({ state: statePda, request, user: user.publicKey })
+        .signers([user])
+        .rpc();
+      await release();
+      expect(await connection.getAccountInfo(hashPda(terms.dataHash))).to.be
+        .null;
+      await openRequest(user, { dataHash: terms.dataHash, dedup: true });
+    });
+  });
+
+  describe("emergency withdraw", () => {
+    const emergencyWithdraw = (recipient = admin.publicKey) =>
+      program.methods
+        .emergencyWithdraw()
+        .accountsPartial({
+          state: statePda,
+          vault: vaultPda,
+          admin: admin.publicKey,
+          recipient,
+        })
+        .rpc({ commitment: "confirmed" });
+    const setRecipient = (recipient: anchor.web3.PublicKey) =>
+      program.methods
+        .setFeeRecipient(recipient)
+        .accountsPartial({ state: statePda, admin: admin.publicKey })
+        .rpc({ commitment: "confirmed" });
+
+    it("is only available while paused", async () => {
+      await expectErr(emergencyWithdraw(), "NotPaused");
+    });
+
+    it("pays the recipient committed before the pause", async () => {
+      await openConfirmed(await funded());
+      await pauseAs(admin);
+      try {
+        // A recipient set after the pause can't receive the evacuation.
+        const treasury = Keypair.generate().publicKey;
+        await setRecipient(treasury);
+        await expectErr(emergencyWithdraw(treasury), "InvalidRecipient");
+        await setRecipient(PublicKey.default);
+
+        const vault = await connection.getAccountInfo(vaultPda);
+        const rent = await connection.getMinimumBalanceForRentExemption(
+          vault.data.length
+        );
+        const sig = await emergencyWithdraw();
+        const [event] = named(await eventsOf(sig), "EmergencyWithdraw");
+        expect(event.data.amount.toNumber()).to.equal(vault.lamports - rent);
+        expect(await connection.getBalance(vaultPda)).to.equal(rent);
+        const state = await program.account.state.fetch(statePda);
+        expect(state.accruedFees.toNumber()).to.equal(0);
+      } finally {
+        await unpause();
+      }
+    });
+  });
+
+  describe("blob updates", () => {
+    const updateBlob = (
+      request: anchor.web3.PublicKey,
+      receipt: Receipt,
+      signedMessage: Buffer
+    ) =>
+      program.methods
+        .updateBlob(
+          receipt.blobId,
+          receipt.suiTxHash,
+          receipt.proofHash,
+          receipt.suiCheckpoint,
+          receipt.deadline,
+          receipt.suiObjectIds
+        )
+        .accountsPartial({
+          state: statePda,
+          request,
+          relayer: relayer.publicKey,
+          instructionSysvar: SYSVAR_INSTRUCTIONS_PUBKEY,
+        })
+        .preInstructions([signIx(relayer, signedMessage)])
+        .rpc({ commitment: "confirmed" });
+
+    it("repoints a confirmed request at a new blob", async () => {
+      const user = await funded();
+      const { request } = await openConfirmed(user);
+      const before = await program.account.request.fetch(request);
+      const receipt = makeReceipt();
+      const nonce = before.nonce.toNumber();
+
+      // A confirmation signature can't be replayed as an update.
+      await expectErr(
+        updateBlob(request, receipt, receiptMessage(request, receipt, nonce)),
+        "InvalidSignature"
+      );
+      const sig = await updateBlob(
+        request,
+        receipt,
+        receiptMessage(request, receipt, nonce, BLOB_UPDATE_DOMAIN)
+      );
+      const [updated] = named(await eventsOf(sig), "BlobUpdated");
+      expect(updated.data.oldBlobId).to.deep.equal(before.blobId);
+      const after = await program.account.request.fetch(request);
+      expect(after.blobId).to.deep.equal(receipt.blobId);
+      expect(after.nonce.toNumber()).to.equal(nonce + 1);
+    });
+
+    it("only updates confirmed requests", async () => {
+      const { request } = await openRequest(await funded());
+      const receipt = makeReceipt();
+      await expectErr(
+        updateBlob(
+          request,
+          receipt,
+          receiptMessage(request, receipt, 0, BLOB_UPDATE_DOMAIN)
+        ),
+        "InvalidStatus"
+      );
+    });
+  });
+
+  describe("compute budget", () => {
+    it("confirms within the compute budget and records the Sui objects", async () => {
+      const user = await funded();
+      const { request } = await openRequest(user);
+      const req = await program.account.request.fetch(request);
+      const objectIds = [bytes(32, 11), bytes(32, 12)];
+      const receipt = makeReceipt(objectIds);
+      const message = receiptMessage(request, receipt, req.nonce.toNumber());
+      const builder = verifyBuilder(request, user.publicKey, receipt, message);
+
+      const tx: anchor.web3.Transaction = await builder.transaction();
+      tx.feePayer = admin.publicKey;
+      tx.recentBlockhash = (await connection.getLatestBlockhash()).blockhash;
+      tx.sign(admin);
+      const simulated = await connection.simulateTransaction(tx);
+      expect(simulated.value.err).to.be.null;
+      console.log("verify_receipt CU:", simulated.value.unitsConsumed);
+      expect(simulated.value.unitsConsumed).to.be.below(VERIFY_CU_BUDGET);
+
+      const vaultBefore = await connection.getBalance(vaultPda);
+      await builder.rpc({ commitment: "confirmed" });
+      const confirmed = await program.account.request.fetch(request);
+      expect(confirmed.status).to.deep.equal({ confirmed: {} });
+      expect(confirmed.suiObjectIds).to.deep.equal(objectIds);
+      expect(await connection.getBalance(vaultPda)).to.equal(
+        vaultBefore + req.payment.toNumber()
+      );
+    });
+  });
+
+  describe("creation fee", () => {
+    const setCreationFee = (fee: number) =>
+      program.methods
+        .setCreationFee(new BN(fee))
+        .accountsPartial({ state: statePda, admin: admin.publicKey })
+        .rpc({ commitment: "confirmed" });
+
+    it("reports the vault balance after the fee lands", async () => {
+      const user = await funded();
+      const { creationFee } = await program.account.state.fetch(statePda);
+      await setCreationFee(5_000);
+      try {
+        const vaultBefore = await connection.getBalance(vaultPda);
+        const { sig } = await openRequest(user);
+        const [requested] = named(await eventsOf(sig), "StorageRequested");
+        expect(requested.data.vaultBalance.toNumber()).to.equal(
+          vaultBefore + 5_000
+        );
+        expect(await connection.getBalance(vaultPda)).to.equal(
+          vaultBefore + 5_000
+        );
+      } finally {
+        await setCreationFee(creationFee.toNumber());
+      }
+    });
+  });
+
+  describe("pausing", () => {
+    const setFlags = (flags: number) =>
+      program.methods
+        .setPauseFlags(flags)
+        .accountsPartial({ state: statePda, admin: admin.publicKey })
+        .rpc({ commitment: "confirmed" });
+    const setQueue = (enabled: boolean) =>
+      program.methods
+        .setQueueWhenPaused(enabled)
+        .accountsPartial({ state: statePda, admin: admin.publicKey })
+        .rpc();
+
+    it("announces every pause flag change", async () => {
+      const sig = await setFlags(PAUSE_INITIATE);
+      const [updated] = named(await eventsOf(sig), "PauseFlagsUpdated");
+      expect(updated.data.oldFlags).to.equal(0);
+      expect(updated.data.newFlags).to.equal(PAUSE_INITIATE);
+      expect(updated.data.authority.toBase58()).to.equal(
+        admin.publicKey.toBase58()
+      );
+      await setFlags(0);
+    });
+
+    it("holds queued requests until initiation is unpaused", async () => {
+      const user = await funded();
+      await setFlags(PAUSE_INITIATE);
+      let request = PublicKey.default;
+      try {
+        await expectErr(openRequest(user), "Paused");
+        await setQueue(true);
+        ({ request } = await openRequest(user));
+        const req = await program.account.request.fetch(request);
+        expect(req.status).to.deep.equal({ queued: {} });
+        await expectErr(confirm(request, user.publicKey), "Paused");
+      } finally {
+        await setFlags(0);
+        await setQueue(false);
+      }
+      await confirm(request, user.publicKey);
+    });
+  });
+
+  describe("state migration", () => {
+    it("refuses to re-migrate a current state", async () => {
+      const before = await program.account.state.fetch(statePda);
+      await expectErr(
+        program.methods
+          .migrateState()
+          .accountsPartial({
+            state: statePda,
+            vault: vaultPda,
+            admin: admin.publicKey,
+            systemProgram: SystemProgram.programId,
+          })
+          .rpc(),
+        "AlreadyMigrated"
+      );
+      const after = await program.account.state.fetch(statePda);
+      expect(after.version).to.equal(before.version);
+      expect(after.minPayment.toNumber()).to.equal(
+        before.minPayment.toNumber()
+      );
+    });
+  });
+
+  describe("access restore", () => {
+    const revoke = (
+      user: anchor.web3.Keypair,
+      request: anchor.web3.PublicKey
+    ) =>
+      program.methods
+        .revokeAccess()
+        .accountsPartial({ state: statePda, request, user: user.publicKey })
+        .signers([user])
+        .rpc({ commitment: "confirmed" });
+    const restore = (
+      user: anchor.web3.Keypair,
+      request: anchor.web3.PublicKey
+    ) =>
+      program.methods
+        .restoreAccess()
+        .accountsPartial({ request, user: user.publicKey })
+        .signers([user])
+        .rpc({ commitment: "confirmed" });
+
+    it("undoes a revoke while the term lasts", async () => {
+      const user = await funded();
+      const { request } = await openConfirmed(user);
+      await expectErr(restore(user, request), "InvalidStatus");
+      await revoke(user, request);
+      await expectErr(restore(await funded(1), request), "Unauthorized");
+
+      const sig = await restore(user, request);
+      expect(named(await eventsOf(sig), "AccessRestored")).to.have.length(1);
+      const req = await program.account.request.fetch(request);
+      expect(req.status).to.deep.equal({ confirmed: {} });
+    });
+  });
+
+  describe("request sweeps", () => {
+    const sweep = (user: anchor.web3.PublicKey, index: number) => {
+      const request = requestPda(user, index);
+      return program.methods
+        .adminSweepRequest(user, new BN(index))
+        .accountsPartial({
+          state: statePda,
+          vault: vaultPda,
+          userState: userStatePda(user),
+          request,
+          escrow: escrowPda(request),
+          admin: admin.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc({ commitment: "confirmed" });
+    };
+    // A reaped address only takes a rent-exempt balance.
+    async function sendDust(to: anchor.web3.PublicKey) {
+      const dust = await connection.getMinimumBalanceForRentExemption(0);
+      await provider.sendAndConfirm(
+        new Transaction().add(
+          SystemProgram.transfer({
+            fromPubkey: admin.publicKey,
+            toPubkey: to,
+            lamports: dust,
+          })
+        )
+      );
+      return dust;
+    }
+
+    it("sweeps dust sent to a closed request's address", async () => {
+      const user = await funded();
+      const { request, escrow, index } = await openRequest(user);
+      await cancel(user, request);
+      await program.methods
+        .closeRequest()
+        .accountsPartial({ request, escrow, user: user.publicKey })
+        .signers([user])
+        .rpc();
+
+      const dust = await sendDust(request);
+      const vaultBefore = await connection.getBalance(vaultPda);
+      await sweep(user.publicKey, index);
+      expect(await connection.getBalance(vaultPda)).to.equal(
+        vaultBefore + dust
+      );
+      expect(await connection.getBalance(request)).to.equal(0);
+    });
+
+    it("won't sweep a live request", async () => {
+      const user = await funded();
+      const { index } = await openRequest(user);
+      await expectErr(sweep(user.publicKey, index), "InvalidAmount");
+    });
+
+    it("won't sweep an index not opened yet", async () => {
+      const user = await funded();
+      const { index } = await openRequest(user);
+      await sendDust(requestPda(user.publicKey, index + 1));
+      await expectErr(sweep(user.publicKey, index + 1), "InvalidSweepTarget");
+    });
+  });
+
+  describe("batch initiation", () => {
+    const item = () => ({
+      dataHash: randomHash(),
+      paymentAmount: new BN(2_000_000),
+      dataSize: new BN(1_024),
+      contentType: contentType("image/png"),
+      encKeyCommitment: bytes(32, 0),
+      tags: [0, 0, 0, 0],
+    });
+    async function openBatch(user: anchor.web3.Keypair, count: number) {
+      const first = await nextIndex(user.publicKey);
+      const requests = [...Array(count).keys()].map((i) =>
+        requestPda(user.publicKey, first + i)
+      );
+      const sig = await program.methods
+        .initiateStorageBatch(requests.map(item), new BN(30 * DAY), 0, 0)
+        .accountsPartial({
+          state: statePda,
+          userState: userStatePda(user.publicKey),
+          vault: vaultPda,
+          user: user.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .remainingAccounts(
+          requests.flatMap((request) => writable(request, escrowPda(request)))
+        )
+        .signers([user])
+        .rpc({ commitment: "confirmed" });
+      return { requests, sig };
+    }
+
+    it("opens every request in the batch", async () => {
+      const user = await funded();
+      const { requests, sig } = await openBatch(user, 2);
+      expect(named(await eventsOf(sig), "StorageRequested")).to.have.length(2);
+      for (const request of requests) {
+        const req = await program.account.request.fetch(request);
+        expect(req.status).to.deep.equal({ pending: {} });
+        expect(req.payment.toNumber()).to.equal(2_000_000);
+      }
+      expect(await nextIndex(user.publicKey)).to.equal(2);
+    });
+
+    it("opens a request whose address was funded ahead of time", async () => {
+      const user = await funded();
+      await openRequest(user);
+      const request = requestPda(user.publicKey, 1);
+      await provider.sendAndConfirm(
+        new Transaction().add(
+          SystemProgram.transfer({
+            fromPubkey: admin.publicKey,
+            toPubkey: request,
+            lamports: 1_000,
+          })
+        )
+      );
+
+      await openBatch(user, 1);
+      const info = await connection.getAccountInfo(request);
+      expect(info.owner.toBase58()).to.equal(program.programId.toBase58());
+      expect(info.lamports).to.equal(
+        await connection.getMinimumBalanceForRentExemption(info.data.length)
+      );
+      const req = await program.account.request.fetch(request);
+      expect(req.status).to.deep.equal({ pending: {} });
+    });
+
+    it("rejects accounts at the wrong addresses", async () => {
+      const user = await funded();
+      const request = requestPda(user.publicKey, 1);
+      await expectErr(
+        program.methods
+          .initiateStorageBatch([item()], new BN(30 * DAY), 0, 0)
+          .accountsPartial({
+            state: statePda,
+            userState: userStatePda(user.publicKey),
+            vault: vaultPda,
+            user: user.publicKey,
+          })
+          .remainingAccounts(writable(request, escrowPda(request)))
+          .signers([user])
+          .rpc(),
+        "InvalidBatch"
+      );
+    });
+  });
+
+  describe("relayer bonds", () => {
+    const setBond = (lamports: number) =>
+      program.methods
+        .setRelayerBond(new BN(lamports))
+        .accountsPartial({ state: statePda, admin: admin.publicKey })
+        .rpc({ commitment: "confirmed" });
+    const slash = (relayer: anchor.web3.PublicKey, amount: number) =>
+      program.methods
+        .slashRelayer(relayer, new BN(amount))
+        .accountsPartial({
+          state: statePda,
+          admin: admin.publicKey,
+          bond: bondPda(relayer),
+          vault: vaultPda,
+        })
+        .rpc({ commitment: "confirmed" });
+    const withdrawBond = (relayer: anchor.web3.Keypair) =>
+      program.methods
+        .withdrawBond()
+        .accountsPartial({
+          state: statePda,
+          bond: bondPda(relayer.publicKey),
+          relayer: relayer.publicKey,
+        })
+        .signers([relayer])
+        .rpc({ commitment: "confirmed" });
+
+    it("slashes into the vault and returns the rest once removed", async () => {
+      const { relayerBond } = await program.account.state.fetch(statePda);
+      await setBond(LAMPORTS_PER_SOL / 2);
+      let relayer: anchor.web3.Keypair;
+      try {
+        relayer = await enrollRelayer();
+      } finally {
+        await setBond(relayerBond.toNumber());
+      }
+      const bond = bondPda(relayer.publicKey);
+      let posted = await program.account.relayerBond.fetch(bond);
+      expect(posted.amount.toNumber()).to.equal(LAMPORTS_PER_SOL / 2);
+
+      const vaultBefore = await connection.getBalance(vaultPda);
+      await slash(relayer.publicKey, 1_000);
+      expect(await connection.getBalance(vaultPda)).to.equal(
+        vaultBefore + 1_000
+      );
+      posted = await program.account.relayerBond.fetch(bond);
+      expect(posted.amount.toNumber()).to.equal(LAMPORTS_PER_SOL / 2 - 1_000);
+      await expectErr(
+        slash(relayer.publicKey, LAMPORTS_PER_SOL),
+        "InsufficientBond"
+      );
+
+      await expectErr(withdrawBond(relayer), "RelayerStillActive");
+      await removeRelayer(relayer.publicKey);
+      const bondLamports = await connection.getBalance(bond);
+      const before = await connection.getBalance(relayer.publicKey);
+      const sig = await withdrawBond(relayer);
+      const [withdrawn] = named(await eventsOf(sig), "BondWithdrawn");
+      expect(withdrawn.data.amount.toNumber()).to.equal(
+        LAMPORTS_PER_SOL / 2 - 1_000
+      );
+      expect(await connection.getBalance(relayer.publicKey)).to.equal(
+        before + bondLamports
+      );
+      expect(await connection.getAccountInfo(bond)).to.be.null;
+    });
+
+    it("only lets the admin slash", async () => {
+      const relayer = await enrollRelayer();
+      const stranger = await funded(1);
+      try {
+        await expectErr(
+          program.methods
+            .slashRelayer(relayer.publicKey, new BN(1))
+            .accountsPartial({
+              state: statePda,
+              admin: stranger.publicKey,
+              bond: bondPda(relayer.publicKey),
+              vault: vaultPda,
+            })
+            .signers([stranger])
+            .rpc(),
+          "ConstraintHasOne"
+        );
+      } finally {
+        await removeRelayer(relayer.publicKey);
+      }
+    });
+  });
+
+  describe("metadata updates", () => {
+    const setPricing = (baseFee: number, perByteFee: number) =>
+      program.methods
+        .setPricing(new BN(baseFee), new BN(perByteFee))
+        .accountsPartial({ state: statePda, admin: admin.publicKey })
+        .rpc({ commitment: "confirmed" });
+    const updateMetadata = (
+      user: anchor.web3.Keypair,
+      request: anchor.web3.PublicKey,
+      refundTo: anchor.web3.PublicKey,
+      dataSize: number
+    ) =>
+      program.methods
+        .updateMetadata(contentType("image/jpeg"), new BN(dataSize))
+        .accountsPartial({
+          state: statePda,
+          request,
+          escrow: escrowPda(request),
+          refundTo,
+          user: user.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc({ commitment: "confirmed" });
+
+    it("refunds a shrink to the request's refund address", async () => {
+      const user = await funded();
+      const refundTo = Keypair.generate().publicKey;
+      const before = await program.account.state.fetch(statePda);
+      await setPricing(0, 4_000);
+      try {
+        const { request } = await openRequest(user, {
+          paymentAmount: new BN(5_000_000),
+          refundTo,
+        });
+        await expectErr(
+          updateMetadata(user, request, user.publicKey, 600),
+          "InvalidRecipient"
+        );
+
+        await updateMetadata(user, request, refundTo, 600);
+        const refunded = 4_000 * (1_024 - 600);
+        expect(await connection.getBalance(refundTo)).to.equal(refunded);
+        const req = await program.account.request.fetch(request);
+        expect(req.dataSize.toNumber()).to.equal(600);
+        expect(req.payment.toNumber()).to.equal(5_000_000 - refunded);
+      } finally {
+        await setPricing(
+          before.baseFee.toNumber(),
+          before.perByteFee.toNumber()
+        );
+      }
+    });
+
+    it("is blocked while initiation is paused", async () => {
+      const user = await funded();
+      const { request } = await openRequest(user);
+      await pauseAs(admin);
+      try {
+        await expectErr(
+          updateMetadata(user, request, user.publicKey, 512),
+          "Paused"
+        );
+      } finally {
+        await unpause();
+      }
+    });
+  });
+
+  describe("merkle receipts", () => {
+    // Mirrors `merkle_root` and `build_merkle_root_message` in lib.rs.
+    const hashPair = (a: Buffer, b: Buffer) =>
+      Buffer.from(
+        keccak_256(Buffer.concat(Buffer.compare(a, b) <= 0 ? [a, b] : [b, a]))
+      );
+    const rootMessage = (root: Buffer) =>
+      Buffer.from(
+        keccak_256(
+          Buffer.concat([
+            MERKLE_ROOT_DOMAIN,
+            program.programId.toBuffer(),
+            root,
+          ])
+        )
+      );
+    const verifyMerkle = (
+      request: anchor.web3.PublicKey,
+      user: anchor.web3.PublicKey,
+      receipt: Receipt,
+      proof: Buffer[],
+      signedMessage: Buffer
+    ) =>
+      program.methods
+        .verifyReceiptMerkle(receipt, proof.map((node) => [...node]))
+        .accountsPartial({
+          state: statePda,
+          request,
+          userState: userStatePda(user),
+          escrow: escrowPda(request),
+          vault: vaultPda,
+          relayer: relayer.publicKey,
+          instructionSysvar: SYSVAR_INSTRUCTIONS_PUBKEY,
+        })
+        .preInstructions([signIx(relayer, signedMessage)])
+        .rpc({ commitment: "confirmed" });
+
+    it("confirms each request under one signed root", async () => {
+      const user = await funded();
+      const opened = [await openRequest(user), await openRequest(user)];
+      const receipts = opened.map(() => makeReceipt());
+      const leaves = opened.map(({ request }, i) =>
+        receiptMessage(request, receipts[i], 0)
+      );
+      const signed = rootMessage(hashPair(leaves[0], leaves[1]));
+
+      await verifyMerkle(
+        opened[0].request,
+        user.publicKey,
+        receipts[0],
+        [leaves[1]],
+        signed
+      );
+      await verifyMerkle(
+        opened[1].request,
+        user.publicKey,
+        receipts[1],
+        [leaves[0]],
+        signed
+      );
+      for (const { request } of opened) {
+        const req = await program.account.request.fetch(request);
+        expect(req.status).to.deep.equal({ confirmed: {} });
+      }
+    });
+
+    it("rejects a tampered proof", async () => {
+      const user = await funded();
+      const { request } = await openRequest(user);
+      const receipt = makeReceipt();
+      const sibling = Buffer.from(randomHash());
+      const leaf = receiptMessage(request, receipt, 0);
+      const signed = rootMessage(hashPair(leaf, sibling));
+      await expectErr(
+        verifyMerkle(
+          request,
+          user.publicKey,
+          receipt,
+          [Buffer.from(randomHash())],
+          signed
+        ),
+        "InvalidSignature"
+      );
+      const req = await program.account.request.fetch(request);
+      expect(req.status).to.deep.equal({ pending: {} });
+    });
+  });
+
+  describe("challenges", () => {
+    const setWindow = (secs: number) =>
+      program.methods
+        .setChallengeWindow(new BN(secs))
+        .accountsPartial({ state: statePda, admin: admin.publicKey })
+        .rpc({ commitment: "confirmed" });
+
+    const challenge = (
+      user: anchor.web3.Keypair,
+      request: anchor.web3.PublicKey
+    ) =>
+      program.methods
+        .challengeRequest()
+        .accountsPartial({ state: statePda, request, user: user.publicKey })
+        .signers([user])
+        .rpc({ commitment: "confirmed" });
+
+    async function answer(request: anchor.web3.PublicKey) {
+      const req = await program.account.request.fetch(request);
+      const receipt = makeReceipt();
+      const message = receiptMessage(request, receipt, req.nonce.toNumber());
+      await program.methods
+        .answerChallenge(receipt)
+        .accountsPartial({
+          state: statePda,
+          request,
+          relayer: relayer.publicKey,
+          instructionSysvar: SYSVAR_INSTRUCTIONS_PUBKEY,
+        })
+        .preInstructions([signIx(relayer, message)])
+        .rpc({ commitment: "confirmed" });
+      return receipt;
+    }
+
+    it("rejects a second challenge while one is pending", async () => {
+      await setWindow(DAY);
+      try {
+        const user = await funded();
+        const { request } = await openConfirmed(user);
+        await challenge(user, request);
+        await expectErr(challenge(user, request), "ChallengePending");
+      } finally {
+        await setWindow(0);
+      }
+    });
+
+    it("answers a challenge on a transferred request", async () => {
+      await setWindow(DAY);
+      try {
+        const user = await funded();
+        const newOwner = await funded();
+        const { request } = await openConfirmed(user);
+        await transfer(user, request, newOwner.publicKey);
+        const userState = await program.account.userState.fetchNullable(
+          userStatePda(newOwner.publicKey)
+        );
+        expect(userState).to.equal(null);
+
+        await challenge(newOwner, request);
+        const receipt = await answer(request);
+
+        const req = await program.account.request.fetch(request);
+        expect(req.status).to.deep.equal({ confirmed: {} });
+        expect(req.challenged).to.equal(false);
+        expect(req.blobId).to.deep.equal(receipt.blobId);
+      } finally {
+        await setWindow(0);
+      }
+    });
+  });
+
+  describe("payment bounds", () => {
+    const setMax = (max: number) =>
+      program.methods
+        .setMaxPayment(new BN(max))
+        .accountsPartial({ state: statePda, admin: admin.publicKey })
+        .rpc({ commitment: "confirmed" });
+
+    it("checks the single setters against the other bound", async () => {
+      const { minPayment } = await program.account.state.fetch(statePda);
+      const min = minPayment.toNumber();
+      await expectErr(setMax(min - 1), "InvalidPaymentBounds");
+      const sig = await setMax(min * 10);
+      try {
+        await expectErr(
+          program.methods
+            .setMinPayment(new BN(min * 10 + 1))
+            .accountsPartial({ state: statePda, admin: admin.publicKey })
+            .rpc(),
+          "InvalidPaymentBounds"
+        );
+        const events = await eventsOf(sig);
+        expect(named(events, "maxPaymentUpdated")).to.have.length(1);
+      } finally {
+        await setMax(0);
+      }
+    });
+
+    it("emits the setters' events from configure", async () => {
+      const { minPayment } = await program.account.state.fetch(statePda);
+      const sig = await program.methods
+        .configure({
+          minPayment,
+          maxPayment: null,
+          baseFee: null,
+          perByteFee: null,
+          feeBps: null,
+          failureFeeBps: null,
+          timeoutSecs: null,
+          confirmWindowSecs: null,
+          maxPendingPerUser: null,
+          maxDataSize: null,
+        })
+        .accountsPartial({ state: statePda, admin: admin.publicKey })
+        .rpc({ commitment: "confirmed" });
+      const events = await eventsOf(sig);
+      expect(named(events, "minPaymentUpdated")).to.have.length(1);
+      expect(named(events, "maxPaymentUpdated")).to.have.length(0);
+    });
+  });
+
+  describe("tags", () => {
+    it("lets the owner retag a request", async () => {
+      const user = await funded();
+      const { request } = await openRequest(user);
+      const req = await program.account.request.fetch(request);
+      expect(req.tags).to.deep.equal([1, 2, 3, 4]);
+
+      const sig = await program.methods
+        .setTags([9, 8, 7, 6])
+        .accountsPartial({ request, user: user.publicKey })
+        .signers([user])
+        .rpc({ commitment: "confirmed" });
+      const after = await program.account.request.fetch(request);
+      expect(after.tags).to.deep.equal([9, 8, 7, 6]);
+      const [updated] = named(await eventsOf(sig), "tagsUpdated");
+      expect(updated.data.tags).to.deep.equal([9, 8, 7, 6]);
+    });
+
+    it("refuses a retag by anyone else", async () => {
+      const user = await funded();
+      const stranger = await funded();
+      const { request } = await openRequest(user);
+      await expectErr(
+        program.methods
+          .setTags([9, 8, 7, 6])
+          .accountsPartial({ request, user: stranger.publicKey })
+          .signers([stranger])
+          .rpc(),
+        "Unauthorized"
+      );
+    });
+  });
+
+  describe("admin status override", () => {
+    let user: anchor.web3.Keypair;
+    const override = (request: anchor.web3.PublicKey, newStatus: object) =>
+      program.methods
+        .adminSetStatus(newStatus as any)
+        .accountsPartial({ state: statePda, request, admin: admin.publicKey })
+        .rpc({ commitment: "confirmed" });
+
+    before(async () => {
+      user = await funded();
+    });
+
+    it("allows fund-neutral moves without touching the counters", async () => {
+      const { request } = await openRequest(user);
+      await confirm(request, user.publicKey);
+      const before = await program.account.state.fetch(statePda);
+
+      const sig = await override(request, { revoked: {} });
+      expect(named(await eventsOf(sig), "StatusOverridden")).to.have.length(1);
+      await override(request, { confirmed: {} });
+
+      const req = await program.account.request.fetch(request);
+      expect(req.status).to.deep.equal({ confirmed: {} });
+      const after = await program.account.state.fetch(statePda);
+      expect(after.confirmedCount.toString()).to.equal(
+        before.confirmedCount.toString()
+      );
+      expect(after.revokedCount.toString()).to.equal(
+        before.revokedCount.toString()
+      );
+    });
+
+    it("rejects moves that would need funds to move", async () => {
+      const { request } = await openRequest(user);
+      const rejected = (newStatus: object) =>
+        expectErr(override(request, newStatus), "InvalidStatusOverride");
+      await rejected({ confirmed: {} });
+      await confirm(request, user.publicKey);
+      await rejected({ failed: {} });
+      await rejected({ pending: {} });
+    });
+
+    it("leaves a challenged request to the challenge", async () => {
+      const setWindow = (secs: number) =>
+        program.methods
+          .setChallengeWindow(new BN(secs))
+          .accountsPartial({ state: statePda, admin: admin.publicKey })
+          .rpc({ commitment: "confirmed" });
+      await setWindow(DAY);
+      try {
+        const { request } = await openConfirmed(user);
+        await program.methods
+          .challengeRequest()
+          .accountsPartial({ state: statePda, request, user: user.publicKey })
+          .signers([user])
+          .rpc({ commitment: "confirmed" });
+        await expectErr(override(request, { revoked: {} }), "ChallengePending");
+      } finally {
+        await setWindow(0);
+      }
+    });
+  });
+
+  describe("sui objects", () => {
+    it("records the Sui objects the receipt names", async () => {
+      const user = await funded();
+      const { request } = await openRequest(user);
+      const objectIds = [bytes(32, 21), bytes(32, 22)];
+      await confirm(request, user.publicKey, objectIds);
+      const req = await program.account.request.fetch(request);
+      expect(req.suiObjectIds).to.deep.equal(objectIds);
+    });
+
+    it("rejects a signature over different Sui objects", async () => {
+      const user = await funded();
+      const { request } = await openRequest(user);
+      const req = await program.account.request.fetch(request);
+      const receipt = makeReceipt();
+      const signed = receiptMessage(
+        request,
+        { ...receipt, suiObjectIds: [bytes(32, 5), bytes(32, 6)] },
+        req.nonce.toNumber()
+      );
+      await expectErr(
+        verifyBuilder(request, user.publicKey, receipt, signed).rpc(),
+        "InvalidSignature"
+      );
+      const after = await program.account.request.fetch(request);
+      expect(after.status).to.deep.equal({ pending: {} });
+    });
+  });
+
+  describe("rate limit", () => {
+    const setInterval = (secs: number) =>
+      program.methods
+        .setMinRequestInterval(new BN(secs))
+        .accountsPartial({ state: statePda, admin: admin.publicKey })
+        .rpc({ commitment: "confirmed" });
+
+    it("rate-limits back-to-back submissions", async () => {
+      const user = await funded();
+      const other = await funded();
+      await setInterval(2);
+      try {
+        await openRequest(user);
+        await expectErr(openRequest(user), "RateLimited");
+        // The interval is per user.
+        await openRequest(other);
+        await sleep(3_000);
+        await openRequest(user);
+      } finally {
+        await setInterval(0);
+      }
+    });
+  });
+
+  describe("withdraw all", () => {
+    const withdrawAll = () =>
+      program.methods
+        .withdrawAll()
+        .accountsPartial({
+          state: statePda,
+          vault: vaultPda,
+          admin: admin.publicKey,
+          recipient: admin.publicKey,
+        })
+        .rpc({ commitment: "confirmed" });
+
+    it("withdraws fees and unlocked revenue together", async () => {
+      const user = await funded();
+      await openConfirmed(user);
+      const revenue = 1_000;
+      await program.methods
+        .queueWithdraw(new BN(revenue))
+        .accountsPartial({ state: statePda, admin: admin.publicKey })
+        .rpc({ commitment: "confirmed" });
+
+      const state = await program.account.state.fetch(statePda);
+      const fees = state.accruedFees.toNumber();
+      expect(fees).to.be.above(0);
+      const vaultBefore = await connection.getBalance(vaultPda);
+      const sig = await withdrawAll();
+
+      const after = await program.account.state.fetch(statePda);
+      expect(after.accruedFees.toNumber()).to.equal(0);
+      expect(after.pendingWithdrawAmount.toNumber()).to.equal(0);
+      expect(await connection.getBalance(vaultPda)).to.equal(
+        vaultBefore - fees - revenue
+      );
+      const events = await eventsOf(sig);
+      const [withdrawn] = named(events, "Withdrawn");
+      expect(withdrawn.data.amount.toNumber()).to.equal(revenue);
+      const [feesWithdrawn] = named(events, "FeesWithdrawn");
+      expect(feesWithdrawn.data.amount.toNumber()).to.equal(fees);
+
+      await expectErr(withdrawAll(), "InvalidAmount");
+    });
+  });
+
+  describe("content types", () => {
+    const setAllowed = (types: number[][]) =>
+      program.methods
+        .setAllowedContentTypes(types)
+        .accountsPartial({ state: statePda, admin: admin.publicKey })
+        .rpc({ commitment: "confirmed" });
+    const empty = () => Array(8).fill(bytes(16, 0));
+
+    it("enforces the content type allowlist when set", async () => {
+      const user = await funded();
+      await setAllowed([contentType("image/png"), ...empty().slice(1)]);
+      try {
+        await openRequest(user, { contentType: contentType("image/png") });
+        await expectErr(
+          openRequest(user, { contentType: contentType("text/plain") }),
+          "UnsupportedContentType"
+        );
+      } finally {
+        await setAllowed(empty());
+      }
+      await openRequest(user, { contentType: contentType("text/plain") });
+    });
+  });
+
+  describe("request ids", () => {
+    const requestCount = (user: anchor.web3.PublicKey) =>
+      program.methods
+        .getUserRequestCount(user)
+        .accountsPartial({ userState: userStatePda(user) })
+        .view()
+        .then((count: BN) => count.toNumber());
+
+    it("derives the id the next request is created at", async () => {
+      const user = await funded();
+      expect(await requestCount(user.publicKey)).to.equal(0);
+
+      const derived = await program.methods
+        .deriveRequestId(user.publicKey, new BN(0))
+        .accountsPartial({ state: statePda })
+        .view();
+      const { request } = await openRequest(user);
+      expect(derived.toBase58()).to.equal(request.toBase58());
+      expect(await requestCount(user.publicKey)).to.equal(1);
+    });
+  });
 });